@@ -2,7 +2,9 @@
 OUTPUT_ARCH(riscv)
 MEMORY
 {
-	SRAM : ORIGIN = 0x08000000, LENGTH = 200K
+	SRAM : ORIGIN = 0x08000000, LENGTH = 196K
+	/* Crash record kept across warm resets, see src/crash.rs */
+	CRASH : ORIGIN = 0x08031000, LENGTH = 4K
 	FLASH : ORIGIN = 0x21000000, LENGTH = 8M
 	RAM : ORIGIN = 0x40000000, LENGTH = 80M
 }
@@ -18,4 +20,14 @@ _max_hart_id = 4;
 _stack_start = ORIGIN(SRAM) + LENGTH(SRAM);
 _hart_stack_size = 4K;
 
+SECTIONS
+{
+	/* NOLOAD keeps it out of the image and out of the .bss clearing */
+	.crash_record (NOLOAD) : ALIGN(8)
+	{
+		KEEP(*(.crash_record));
+	} > CRASH
+}
+INSERT AFTER .bss;
+
 INCLUDE link.x
@@ -22,6 +22,10 @@ impl<T, const N: usize> ArrayVec<T, N> {
         self.length = 0;
     }
 
+    pub fn is_full(&self) -> bool {
+        self.length == N
+    }
+
     pub fn try_push(&mut self, value: T) -> Result<(), T> {
         if self.length == N {
             return Err(value);
@@ -1,7 +1,12 @@
 use embedded_hal::digital::OutputPin;
 use jh7110_hal::gpio;
 use jh7110_pac as pac;
-use riscv::interrupt::machine::Interrupt;
+
+use crate::{clint, mono::Duration, plic::HartId};
+
+/// Time the LED stays on and off
+const BLINK_PERIOD: Duration = Duration::from_millis(250);
+
 static mut PIN_IS_HIGH: bool = false;
 
 pub fn configure() {
@@ -13,17 +18,15 @@ pub fn configure() {
     let mut gpio40_out = gpio40.into_enabled_output();
 
     unsafe {
-        let mtimecmp = 0x0200_4008 as *mut u64;
-        let mtime = 0x0200_bff8 as *const u64;
-        //quarter second .25s/(1/4MHz) = 1000000
-        mtimecmp.write_volatile(mtime.read_volatile() + 1_000_000);
         gpio40_out.set_low().ok();
         PIN_IS_HIGH = false;
     }
+    //Blink from the machine timer of the hart configuring it
+    clint::set_timer_handler(HartId::current(), Some(toggle));
+    clint::schedule_in(BLINK_PERIOD);
 }
 
-#[riscv_rt::core_interrupt(Interrupt::MachineTimer)]
-fn machine_timer_isr() {
+fn toggle() {
     let peripherals = unsafe { pac::Peripherals::steal() };
     // configure GPIO 40 as an output
     let gpio40 = gpio::get_gpio(peripherals.sys_pinctrl.padcfg().gpio40());
@@ -42,9 +45,5 @@ fn machine_timer_isr() {
         }
     }
 
-    let mtimecmp = 0x0200_4008 as *mut u64;
-    let mtime = 0x0200_bff8 as *const u64;
-    unsafe {
-        mtimecmp.write_volatile(mtime.read_volatile() + 1_000_000);
-    }
+    clint::schedule_in(BLINK_PERIOD);
 }
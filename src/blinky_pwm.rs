@@ -3,7 +3,7 @@ use jh7110_hal as hal;
 use jh7110_pac::{self as pac, Interrupt};
 
 use crate::{
-    default_isr_this_has_to_be_wrong::{enable_interrupt, InterruptPriority},
+    default_isr_this_has_to_be_wrong::{enable_interrupt, register_handler_fn, InterruptPriority},
     println,
 };
 
@@ -70,11 +70,12 @@ pub fn configure() {
             .clear_bit() //disable active pull down capability
     });
 
+    if register_handler_fn(Interrupt::PTC0, ptc0).is_err() {
+        println!("PTC0 handler already registered");
+    }
     enable_interrupt(Interrupt::PTC0, InterruptPriority::Priority7)
 }
 
-pac::interrupt!(PTC0, ptc0);
-#[no_mangle]
 fn ptc0() {
     let p = unsafe { pac::Peripherals::steal() };
     //Set the low and high counter values
@@ -0,0 +1,126 @@
+//! Driver for the CLINT, the core local interruptor.
+//!
+//! The CLINT holds a machine software interrupt pending bit (`msip`) and a timer
+//! compare register (`mtimecmp`) for each of the five harts, and the `mtime`
+//! counter they all share.  A hart's `MachineTimer` interrupt is pending while
+//! `mtime >= mtimecmp`.
+//!
+//! [machine_timer_isr] runs the handler set for the hart it fires on with
+//! [set_timer_handler].  The handler is called with the compare already
+//! cancelled, it reschedules itself with [schedule_in] or [schedule_at] if it
+//! wants to run again.
+use core::{marker::PhantomData, ptr};
+
+use riscv::interrupt::machine::Interrupt;
+
+use crate::{
+    interrupt_stats::{self, Source},
+    mono::{Duration, Instant},
+    plic::{HartId, NUMBER_HARTS},
+};
+
+/// CLINT base address
+const CLINT_BASE: usize = 0x0200_0000;
+/// Machine software interrupt pending registers, one 32 bit register per hart
+const MSIP_OFFSET: usize = 0x0000;
+/// Timer compare registers, one 64 bit register per hart
+const MTIMECMP_OFFSET: usize = 0x4000;
+
+const fn msip_address(hart: HartId) -> usize {
+    CLINT_BASE + MSIP_OFFSET + 4 * hart as usize
+}
+
+const fn mtimecmp_address(hart: HartId) -> usize {
+    CLINT_BASE + MTIMECMP_OFFSET + 8 * hart as usize
+}
+
+/// Struct to access the CLINT
+pub struct Clint {
+    _marker: PhantomData<*const ()>,
+}
+
+impl Clint {
+    pub fn new() -> Self {
+        Self {
+            _marker: PhantomData,
+        }
+    }
+
+    /// Raises the software interrupt of the hart
+    pub fn set_msip(&self, hart: HartId) {
+        unsafe { ptr::write_volatile(msip_address(hart) as *mut u32, 1) };
+    }
+
+    /// Clears the software interrupt of the hart
+    pub fn clear_msip(&self, hart: HartId) {
+        unsafe { ptr::write_volatile(msip_address(hart) as *mut u32, 0) };
+    }
+
+    /// Checks if the software interrupt of the hart is raised
+    pub fn is_msip_pending(&self, hart: HartId) -> bool {
+        unsafe { ptr::read_volatile(msip_address(hart) as *const u32) & 1 != 0 }
+    }
+
+    /// Reads the shared machine time
+    pub fn mtime(&self) -> Instant {
+        Instant::now()
+    }
+
+    /// Reads the timer compare of the hart
+    pub fn mtimecmp(&self, hart: HartId) -> Instant {
+        Instant::from_ticks(unsafe { ptr::read_volatile(mtimecmp_address(hart) as *const u64) })
+    }
+
+    /// Sets the timer compare of the hart, its timer interrupt is pending from
+    /// `deadline` on
+    pub fn set_mtimecmp(&self, hart: HartId, deadline: Instant) {
+        unsafe { ptr::write_volatile(mtimecmp_address(hart) as *mut u64, deadline.ticks()) };
+    }
+
+    /// Pushes the timer compare of the hart out of reach, which also clears a
+    /// pending timer interrupt
+    pub fn cancel_mtimecmp(&self, hart: HartId) {
+        unsafe { ptr::write_volatile(mtimecmp_address(hart) as *mut u64, u64::MAX) };
+    }
+}
+
+/// Raises the `MachineTimer` interrupt of the hart executing this code at
+/// `deadline`, replacing a compare that is still pending
+pub fn schedule_at(deadline: Instant) {
+    Clint::new().set_mtimecmp(HartId::current(), deadline);
+}
+
+/// Raises the `MachineTimer` interrupt of the hart executing this code after
+/// `delay`
+pub fn schedule_in(delay: Duration) {
+    schedule_at(Instant::now() + delay);
+}
+
+/// Cancels the pending compare of the hart executing this code
+pub fn cancel() {
+    Clint::new().cancel_mtimecmp(HartId::current());
+}
+
+const NO_HANDLER: spin::Mutex<Option<fn()>> = spin::Mutex::new(None);
+static TIMER_HANDLERS: [spin::Mutex<Option<fn()>>; NUMBER_HARTS] = [NO_HANDLER; NUMBER_HARTS];
+
+/// Sets the function the `MachineTimer` interrupt of the hart runs, None
+/// removes it
+pub fn set_timer_handler(hart: HartId, handler: Option<fn()>) {
+    riscv::interrupt::free(|| {
+        *TIMER_HANDLERS[hart as usize].lock() = handler;
+    });
+}
+
+#[riscv_rt::core_interrupt(Interrupt::MachineTimer)]
+fn machine_timer_isr() {
+    let start = interrupt_stats::start();
+    let hart = HartId::current();
+    //Cancel first, the interrupt stays pending until mtimecmp moves past mtime
+    Clint::new().cancel_mtimecmp(hart);
+    let handler = *TIMER_HANDLERS[hart as usize].lock();
+    if let Some(handler) = handler {
+        handler();
+    }
+    interrupt_stats::record(Source::MachineTimer, start);
+}
@@ -0,0 +1,279 @@
+//! Crash record preserved across a warm reset.
+//!
+//! The exception and panic handlers and the watchdog pre-timeout interrupt
+//! write a [CrashRecord] into the `CRASH` region at the top of SRAM (see
+//! `memory.x`).  The section is `NOLOAD`, so neither the UART boot nor the
+//! `.bss` clearing of the next boot touches it, and [take] can print what
+//! happened before the reset.  A magic number and a checksum tell a record
+//! apart from whatever the SRAM held at power on, they are checked on the raw
+//! bytes before the region is read as a record.
+use core::{
+    fmt,
+    mem::{self, MaybeUninit},
+    ptr,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use riscv::register::{mcause, mepc, mstatus, mtval};
+
+use crate::{log, mono::Instant, plic::HartId};
+
+/// Marks a record as written by [record_exception] or [record_panic]
+const MAGIC: u32 = 0xC4A5_4ED0;
+/// Bytes of the cause message kept
+const MESSAGE_SIZE: usize = 96;
+/// Bytes of the log history kept
+const LOG_SIZE: usize = log::HISTORY_SIZE;
+/// Names of the [riscv_rt::TrapFrame] registers in the order they are kept
+const REGISTER_NAMES: [&str; NUMBER_REGISTERS] = [
+    "ra", "t0", "t1", "t2", "t3", "t4", "t5", "t6", "a0", "a1", "a2", "a3", "a4", "a5", "a6", "a7",
+];
+const NUMBER_REGISTERS: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cause {
+    Exception,
+    Panic,
+    /// The watchdog was about to reset the SoC
+    Watchdog,
+    Unknown(u32),
+}
+
+impl From<u32> for Cause {
+    fn from(value: u32) -> Self {
+        match value {
+            1 => Cause::Exception,
+            2 => Cause::Panic,
+            3 => Cause::Watchdog,
+            value => Cause::Unknown(value),
+        }
+    }
+}
+
+impl From<Cause> for u32 {
+    fn from(cause: Cause) -> Self {
+        match cause {
+            Cause::Exception => 1,
+            Cause::Panic => 2,
+            Cause::Watchdog => 3,
+            Cause::Unknown(value) => value,
+        }
+    }
+}
+
+/// Everything kept about a crash.  The fields are laid out without padding so
+/// the checksum covers every byte.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct CrashRecord {
+    magic: u32,
+    checksum: u32,
+    cause: u32,
+    hart: u32,
+    /// mtime when the crash was recorded
+    timestamp: u64,
+    mcause: usize,
+    mepc: usize,
+    mtval: usize,
+    mstatus: usize,
+    /// Trap frame registers, all zero for a panic
+    registers: [usize; NUMBER_REGISTERS],
+    message_length: u32,
+    log_length: u32,
+    message: [u8; MESSAGE_SIZE],
+    log: [u8; LOG_SIZE],
+}
+
+#[link_section = ".crash_record"]
+static mut RECORD: MaybeUninit<CrashRecord> = MaybeUninit::uninit();
+/// Set once a crash was recorded during this boot, only the first one is kept
+/// as later ones are usually a consequence of it
+static RECORDED: AtomicBool = AtomicBool::new(false);
+
+/// FNV-1a over every byte after the checksum field.  Reads through the pointer
+/// byte by byte, so it also works on memory that doesn't hold a record yet.
+fn checksum(record: *const CrashRecord) -> u32 {
+    let start = mem::offset_of!(CrashRecord, cause);
+    let bytes = unsafe { (record as *const u8).add(start) };
+    (0..mem::size_of::<CrashRecord>() - start).fold(0x811C_9DC5, |hash, i| {
+        let byte = unsafe { ptr::read_volatile(bytes.add(i)) };
+        (hash ^ byte as u32).wrapping_mul(0x0100_0193)
+    })
+}
+
+/// The record region, which may hold anything until a record was validated
+fn stored() -> *const CrashRecord {
+    ptr::addr_of!(RECORD).cast::<CrashRecord>()
+}
+
+/// Checks magic and checksum of the record region without reading it as a
+/// [CrashRecord]
+fn is_valid() -> bool {
+    let record = stored();
+    let magic = unsafe { ptr::read_volatile(ptr::addr_of!((*record).magic)) };
+    let stored_checksum = unsafe { ptr::read_volatile(ptr::addr_of!((*record).checksum)) };
+    magic == MAGIC && stored_checksum == checksum(record)
+}
+
+/// Formats into a fixed buffer, dropping whatever doesn't fit
+struct MessageWriter<'a> {
+    buffer: &'a mut [u8],
+    length: usize,
+}
+
+impl fmt::Write for MessageWriter<'_> {
+    fn write_str(&mut self, s: &str) -> Result<(), fmt::Error> {
+        let count = s.len().min(self.buffer.len() - self.length);
+        self.buffer[self.length..self.length + count].copy_from_slice(&s.as_bytes()[..count]);
+        self.length += count;
+        Ok(())
+    }
+}
+
+fn write(cause: Cause, trap_frame: Option<&riscv_rt::TrapFrame>, message: fmt::Arguments) {
+    if RECORDED.swap(true, Ordering::Relaxed) {
+        return;
+    }
+    let mut record = CrashRecord {
+        magic: MAGIC,
+        checksum: 0,
+        cause: cause.into(),
+        hart: HartId::current() as u32,
+        timestamp: Instant::now().ticks(),
+        mcause: mcause::read().bits(),
+        mepc: mepc::read(),
+        mtval: mtval::read(),
+        mstatus: mstatus::read().bits(),
+        registers: [0; NUMBER_REGISTERS],
+        message_length: 0,
+        log_length: 0,
+        message: [0; MESSAGE_SIZE],
+        log: [0; LOG_SIZE],
+    };
+    if let Some(f) = trap_frame {
+        record.registers = [
+            f.ra, f.t0, f.t1, f.t2, f.t3, f.t4, f.t5, f.t6, f.a0, f.a1, f.a2, f.a3, f.a4, f.a5,
+            f.a6, f.a7,
+        ];
+    }
+    let mut writer = MessageWriter {
+        buffer: &mut record.message,
+        length: 0,
+    };
+    fmt::Write::write_fmt(&mut writer, message).ok();
+    record.message_length = writer.length as u32;
+    record.log_length = log::copy_history(&mut record.log) as u32;
+    record.checksum = checksum(&record);
+    unsafe {
+        ptr::write_volatile(ptr::addr_of_mut!(RECORD), MaybeUninit::new(record));
+    }
+}
+
+/// Records an exception taken with `trap_frame`, `message` describes it
+pub fn record_exception(trap_frame: &riscv_rt::TrapFrame, message: fmt::Arguments) {
+    write(Cause::Exception, Some(trap_frame), message);
+}
+
+/// Records a panic, `message` describes it
+pub fn record_panic(message: fmt::Arguments) {
+    write(Cause::Panic, None, message);
+}
+
+/// Records that the watchdog is about to reset the SoC.  Called from its
+/// interrupt, so `mepc` holds the code the interrupt hit.
+pub fn record_watchdog(message: fmt::Arguments) {
+    write(Cause::Watchdog, None, message);
+}
+
+/// Drops a watchdog record written during this boot, the watchdog got fed after
+/// all
+pub fn discard_watchdog() {
+    riscv::interrupt::free(|| {
+        //Only look at the record once this boot wrote it
+        if RECORDED.load(Ordering::Relaxed)
+            && unsafe { ptr::read_volatile(ptr::addr_of!((*stored()).cause)) }
+                == u32::from(Cause::Watchdog)
+        {
+            unsafe {
+                ptr::write_volatile(ptr::addr_of_mut!(RECORD).cast::<u32>(), 0);
+            }
+            RECORDED.store(false, Ordering::Relaxed);
+        }
+    });
+}
+
+/// Takes the record left by the previous boot, if there is a valid one, and
+/// clears it so it's only reported once
+pub fn take() -> Option<CrashRecord> {
+    //The region is only read as a record once magic and checksum show a
+    //previous boot wrote one
+    let record = match is_valid() {
+        true => Some(unsafe { ptr::read_volatile(stored()) }),
+        false => None,
+    };
+    unsafe {
+        ptr::write_volatile(ptr::addr_of_mut!(RECORD).cast::<u32>(), 0);
+    }
+    record
+}
+
+impl CrashRecord {
+    pub fn cause(&self) -> Cause {
+        Cause::from(self.cause)
+    }
+
+    pub fn hart(&self) -> HartId {
+        HartId::from(self.hart as usize)
+    }
+
+    pub fn timestamp(&self) -> Instant {
+        Instant::from_ticks(self.timestamp)
+    }
+
+    pub fn message(&self) -> &[u8] {
+        &self.message[..(self.message_length as usize).min(MESSAGE_SIZE)]
+    }
+
+    pub fn log(&self) -> &[u8] {
+        &self.log[..(self.log_length as usize).min(LOG_SIZE)]
+    }
+}
+
+/// Writes the bytes as text, replacing anything that isn't printable ASCII
+fn write_text(f: &mut fmt::Formatter, bytes: &[u8]) -> fmt::Result {
+    for &byte in bytes {
+        match byte {
+            b'\r' | b'\n' | b' '..=b'~' => fmt::Write::write_char(f, byte as char)?,
+            _ => fmt::Write::write_char(f, '?')?,
+        }
+    }
+    Ok(())
+}
+
+impl fmt::Display for CrashRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:?} on {:?} at {}: ",
+            self.cause(),
+            self.hart(),
+            self.timestamp()
+        )?;
+        write_text(f, self.message())?;
+        write!(f, "\r\nmcause:  {:#018x}", self.mcause)?;
+        write!(f, "\r\nmepc:    {:#018x}", self.mepc)?;
+        write!(f, "\r\nmtval:   {:#018x}", self.mtval)?;
+        write!(f, "\r\nmstatus: {:#018x}", self.mstatus)?;
+        if self.cause() == Cause::Exception {
+            for (i, (name, value)) in REGISTER_NAMES.iter().zip(self.registers).enumerate() {
+                let separator = match i % 4 {
+                    0 => "\r\n",
+                    _ => " ",
+                };
+                write!(f, "{}{:<2} {:#018x}", separator, name, value)?;
+            }
+        }
+        write!(f, "\r\nlast log:\r\n")?;
+        write_text(f, self.log())
+    }
+}
@@ -0,0 +1,602 @@
+//! Debounce state machine of one input, free of registers and printing.
+//!
+//! The caller samples the input and feeds the level in with every edge
+//! interrupt, every debounce tick or at the settle deadline.  Each call returns
+//! the level to report, if any.  Reported levels always alternate, and once the
+//! input settled the last reported level is the level it settled on.  A glitch
+//! reported on [ReportMode::FirstEdge] is taken back when the input settles on
+//! the old level.
+
+use crate::mono::{Duration, Instant};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum LogicState {
+    Low,
+    High,
+    Unknown,
+}
+
+impl From<bool> for LogicState {
+    fn from(is_high: bool) -> Self {
+        match is_high {
+            false => LogicState::Low,
+            true => LogicState::High,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum InputSignalState {
+    Unknown,
+    StableLow,
+    StabilizingHigh,
+    StableHigh,
+    StabilizingLow,
+}
+
+/// When a debounced edge is reported
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ReportMode {
+    /// Right on the first edge away from a stable level, bounces after it are
+    /// swallowed.  Fast, but a glitch is reported as a press.
+    FirstEdge,
+    /// Once the signal settled on a new level, a settle time late
+    AfterSettle,
+}
+
+/// How a signal is debounced
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct DebounceConfig {
+    /// Time without an edge until the signal counts as stable
+    pub settle_ms: u32,
+    /// Bounces in one stabilization after which the signal is flagged noisy,
+    /// None never flags it
+    pub max_bounces: Option<u8>,
+    pub report: ReportMode,
+}
+
+impl DebounceConfig {
+    /// Mechanical buttons and switches, bouncing for up to tens of ms
+    pub const MECHANICAL: DebounceConfig = DebounceConfig {
+        settle_ms: 50,
+        max_bounces: Some(20),
+        report: ReportMode::FirstEdge,
+    };
+    /// Reed switches, bouncing shorter but chattering near the magnet
+    pub const REED: DebounceConfig = DebounceConfig {
+        settle_ms: 10,
+        max_bounces: Some(10),
+        report: ReportMode::AfterSettle,
+    };
+    /// Inputs driven by logic, which don't bounce
+    pub const CLEAN: DebounceConfig = DebounceConfig {
+        settle_ms: 1,
+        max_bounces: Some(1),
+        report: ReportMode::FirstEdge,
+    };
+
+    pub fn settle_time(&self) -> Duration {
+        Duration::from_millis(self.settle_ms as u64)
+    }
+
+    /// Ticks of `tick_period` without an edge until the signal counts as
+    /// stable, at least one
+    pub fn stable_ticks(&self, tick_period: Duration) -> u16 {
+        let period_ms = (tick_period.as_millis() as u32).max(1);
+        let ticks = self.settle_ms.div_ceil(period_ms);
+        ticks.clamp(1, u16::MAX as u32) as u16
+    }
+}
+
+impl Default for DebounceConfig {
+    fn default() -> Self {
+        DebounceConfig::MECHANICAL
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Debouncer {
+    state: InputSignalState,
+    stabilization_counter: u16,
+    /// Last level reported, Unknown until the signal first settled
+    reported: LogicState,
+    /// When the last edge was seen
+    last_edge: Instant,
+    config: DebounceConfig,
+    /// Edges since the signal started stabilizing
+    bounces: u8,
+    /// Set once more than [DebounceConfig::max_bounces] edges came in while
+    /// stabilizing, cleared by [Debouncer::take_noisy]
+    noisy: bool,
+}
+
+impl Debouncer {
+    pub const fn new(config: DebounceConfig) -> Self {
+        Self {
+            state: InputSignalState::Unknown,
+            stabilization_counter: 0,
+            reported: LogicState::Unknown,
+            last_edge: Instant::ZERO,
+            config,
+            bounces: 0,
+            noisy: false,
+        }
+    }
+
+    pub fn state(&self) -> InputSignalState {
+        self.state
+    }
+
+    /// Last level reported
+    pub fn level(&self) -> LogicState {
+        self.reported
+    }
+
+    pub fn config(&self) -> DebounceConfig {
+        self.config
+    }
+
+    pub fn is_stable(&self) -> bool {
+        matches!(
+            self.state,
+            InputSignalState::StableLow | InputSignalState::StableHigh
+        )
+    }
+
+    /// Level the signal is stable at, None while it isn't
+    pub fn stable_level(&self) -> Option<LogicState> {
+        match self.state {
+            InputSignalState::StableLow => Some(LogicState::Low),
+            InputSignalState::StableHigh => Some(LogicState::High),
+            _ => None,
+        }
+    }
+
+    /// When the signal counts as settled if no other edge comes in, None while
+    /// it's stable
+    pub fn settle_deadline(&self) -> Option<Instant> {
+        match self.is_stable() {
+            true => None,
+            false => Some(self.last_edge + self.config.settle_time()),
+        }
+    }
+
+    /// Checks if the signal was flagged noisy since the last call
+    pub fn take_noisy(&mut self) -> bool {
+        core::mem::replace(&mut self.noisy, false)
+    }
+
+    /// Handles an edge with the level sampled right after it.  `moved_to` is
+    /// the level the interrupt says the input went to, known for single edge
+    /// and level senses and None for both edges.  Returns the level to report
+    /// if the edge is the first one away from a stable level and the signal
+    /// reports on [ReportMode::FirstEdge].
+    pub fn process_edge(
+        &mut self,
+        level: LogicState,
+        moved_to: Option<LogicState>,
+        now: Instant,
+    ) -> Option<LogicState> {
+        self.last_edge = now;
+        self.stabilization_counter = 0;
+        if level == LogicState::Unknown {
+            return None;
+        }
+        match (self.state, moved_to) {
+            (InputSignalState::Unknown, _) => {
+                self.start_stabilizing(level);
+                None
+            }
+            //The edge back to the stable level raised no interrupt and was
+            //missed, go by the sample
+            (InputSignalState::StableLow, Some(LogicState::Low))
+            | (InputSignalState::StableHigh, Some(LogicState::High)) => {
+                self.revalidate(level);
+                None
+            }
+            //An edge away from a stable level changed it, whatever the sample
+            //bounced back to
+            (InputSignalState::StableLow, _) => {
+                self.bounces = 0;
+                self.state = InputSignalState::StabilizingHigh;
+                self.report_first_edge(LogicState::High)
+            }
+            (InputSignalState::StableHigh, _) => {
+                self.bounces = 0;
+                self.state = InputSignalState::StabilizingLow;
+                self.report_first_edge(LogicState::Low)
+            }
+            //Follow the level the bounce left the input at
+            (InputSignalState::StabilizingLow | InputSignalState::StabilizingHigh, _) => {
+                self.count_bounce();
+                self.start_stabilizing(level);
+                None
+            }
+        }
+    }
+
+    /// Handles a debounce tick of `tick_period`.  Returns the level to report
+    /// once the signal settled on a level different from the last reported one.
+    pub fn process_debounce_tick(
+        &mut self,
+        level: LogicState,
+        tick_period: Duration,
+    ) -> Option<LogicState> {
+        if level == LogicState::Unknown {
+            return None;
+        }
+        match self.state {
+            InputSignalState::Unknown => self.start_stabilizing(level),
+            InputSignalState::StabilizingLow | InputSignalState::StabilizingHigh => {
+                self.stabilization_counter += 1;
+                if self.stabilization_counter >= self.config.stable_ticks(tick_period) {
+                    return self.settle(level);
+                }
+            }
+            InputSignalState::StableLow | InputSignalState::StableHigh => {
+                self.revalidate(level);
+            }
+        }
+        None
+    }
+
+    /// Settles the signal once no edge came in for the settle time since the
+    /// last one.  Returns the level to report like
+    /// [Debouncer::process_debounce_tick].
+    pub fn process_settle_deadline(
+        &mut self,
+        level: LogicState,
+        now: Instant,
+    ) -> Option<LogicState> {
+        if level == LogicState::Unknown {
+            return None;
+        }
+        match self.state {
+            InputSignalState::Unknown => {
+                self.start_stabilizing(level);
+                self.last_edge = now;
+            }
+            InputSignalState::StabilizingLow | InputSignalState::StabilizingHigh => {
+                if now.duration_since(self.last_edge) >= self.config.settle_time() {
+                    //Settling on the wrong level starts over
+                    self.last_edge = now;
+                    return self.settle(level);
+                }
+            }
+            InputSignalState::StableLow | InputSignalState::StableHigh => {
+                if self.revalidate(level) {
+                    self.last_edge = now;
+                }
+            }
+        }
+        None
+    }
+
+    fn report_first_edge(&mut self, level: LogicState) -> Option<LogicState> {
+        match self.config.report {
+            ReportMode::FirstEdge => self.report(level),
+            ReportMode::AfterSettle => None,
+        }
+    }
+
+    /// Records the level as reported, None if it already was
+    fn report(&mut self, level: LogicState) -> Option<LogicState> {
+        if level == self.reported {
+            return None;
+        }
+        self.reported = level;
+        Some(level)
+    }
+
+    /// Counts an edge while stabilizing, flagging the signal noisy past the
+    /// maximum
+    fn count_bounce(&mut self) {
+        self.bounces = self.bounces.saturating_add(1);
+        if let Some(max) = self.config.max_bounces {
+            if self.bounces > max {
+                self.noisy = true;
+            }
+        }
+    }
+
+    /// Moves a stabilizing signal to stable if it's still at the level it was
+    /// stabilizing to, otherwise to stabilizing to the sampled level.  Returns
+    /// the new level if it differs from the last reported one.  The first
+    /// level the signal settles on is its initial level and isn't reported.
+    fn settle(&mut self, level: LogicState) -> Option<LogicState> {
+        self.stabilization_counter = 0;
+        let stable = match (self.state, level) {
+            (InputSignalState::StabilizingLow, LogicState::Low) => InputSignalState::StableLow,
+            (InputSignalState::StabilizingHigh, LogicState::High) => InputSignalState::StableHigh,
+            _ => {
+                self.start_stabilizing(level);
+                return None;
+            }
+        };
+        self.state = stable;
+        self.bounces = 0;
+        if self.reported == LogicState::Unknown {
+            self.reported = level;
+            return None;
+        }
+        self.report(level)
+    }
+
+    /// Starts stabilizing again if a stable signal isn't at its level anymore,
+    /// an edge was missed.  Returns true if it did.
+    fn revalidate(&mut self, level: LogicState) -> bool {
+        let expected = match self.state {
+            InputSignalState::StableLow => LogicState::Low,
+            InputSignalState::StableHigh => LogicState::High,
+            _ => return false,
+        };
+        if level == expected {
+            return false;
+        }
+        self.bounces = 0;
+        self.start_stabilizing(level);
+        true
+    }
+
+    /// Set to stabilizing towards the sampled level
+    fn start_stabilizing(&mut self, level: LogicState) {
+        self.stabilization_counter = 0;
+        match level {
+            LogicState::Low => self.state = InputSignalState::StabilizingLow,
+            LogicState::High => self.state = InputSignalState::StabilizingHigh,
+            LogicState::Unknown => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use LogicState::{High, Low};
+
+    const TICK: Duration = Duration::from_millis(10);
+
+    fn at(ms: u64) -> Instant {
+        Instant::ZERO + Duration::from_millis(ms)
+    }
+
+    /// Debouncer stable at `level` after the settle deadlines of
+    /// `DebounceMode::Timestamp`, without reporting it
+    fn stable_at(config: DebounceConfig, level: LogicState) -> Debouncer {
+        let mut debouncer = Debouncer::new(config);
+        assert_eq!(debouncer.process_settle_deadline(level, at(0)), None);
+        let settled = at(config.settle_ms as u64);
+        assert_eq!(debouncer.process_settle_deadline(level, settled), None);
+        assert_eq!(debouncer.stable_level(), Some(level));
+        assert_eq!(debouncer.level(), level);
+        debouncer
+    }
+
+    #[test]
+    fn first_edge_from_unknown_follows_the_sample() {
+        let mut debouncer = Debouncer::new(DebounceConfig::MECHANICAL);
+        assert_eq!(debouncer.process_edge(High, None, at(0)), None);
+        assert_eq!(debouncer.state(), InputSignalState::StabilizingHigh);
+        let mut debouncer = Debouncer::new(DebounceConfig::MECHANICAL);
+        assert_eq!(debouncer.process_edge(Low, None, at(0)), None);
+        assert_eq!(debouncer.state(), InputSignalState::StabilizingLow);
+    }
+
+    #[test]
+    fn timestamp_first_edge_reports_once() {
+        let mut debouncer = stable_at(DebounceConfig::MECHANICAL, Low);
+        assert_eq!(debouncer.process_edge(High, None, at(100)), Some(High));
+        //Bounces after the first edge are swallowed
+        assert_eq!(debouncer.process_edge(Low, None, at(102)), None);
+        assert_eq!(debouncer.process_edge(High, None, at(104)), None);
+        assert_eq!(debouncer.settle_deadline(), Some(at(154)));
+        //Too early, the last bounce was less than the settle time ago
+        assert_eq!(debouncer.process_settle_deadline(High, at(120)), None);
+        assert!(!debouncer.is_stable());
+        assert_eq!(debouncer.process_settle_deadline(High, at(154)), None);
+        assert_eq!(debouncer.stable_level(), Some(High));
+        assert_eq!(debouncer.settle_deadline(), None);
+    }
+
+    #[test]
+    fn timestamp_first_edge_takes_back_a_glitch() {
+        let mut debouncer = stable_at(DebounceConfig::MECHANICAL, Low);
+        assert_eq!(debouncer.process_edge(Low, None, at(100)), Some(High));
+        assert_eq!(debouncer.process_settle_deadline(Low, at(150)), None);
+        assert_eq!(debouncer.process_settle_deadline(Low, at(200)), Some(Low));
+        assert_eq!(debouncer.stable_level(), Some(Low));
+    }
+
+    #[test]
+    fn timestamp_after_settle_reports_once_settled() {
+        let mut debouncer = stable_at(DebounceConfig::REED, Low);
+        assert_eq!(debouncer.process_edge(High, None, at(100)), None);
+        assert_eq!(debouncer.process_edge(Low, None, at(101)), None);
+        assert_eq!(debouncer.process_edge(High, None, at(102)), None);
+        assert_eq!(debouncer.process_settle_deadline(High, at(112)), Some(High));
+        assert_eq!(debouncer.level(), High);
+    }
+
+    #[test]
+    fn timestamp_after_settle_ignores_a_glitch() {
+        let mut debouncer = stable_at(DebounceConfig::REED, Low);
+        assert_eq!(debouncer.process_edge(High, None, at(100)), None);
+        assert_eq!(debouncer.process_edge(Low, None, at(101)), None);
+        assert_eq!(debouncer.process_settle_deadline(Low, at(111)), None);
+        assert_eq!(debouncer.stable_level(), Some(Low));
+    }
+
+    #[test]
+    fn tick_first_edge() {
+        let config = DebounceConfig::MECHANICAL;
+        let stable_ticks = config.stable_ticks(TICK);
+        assert_eq!(stable_ticks, 5);
+        let mut debouncer = Debouncer::new(config);
+        for _ in 0..=stable_ticks {
+            assert_eq!(debouncer.process_debounce_tick(Low, TICK), None);
+        }
+        assert_eq!(debouncer.stable_level(), Some(Low));
+        assert_eq!(debouncer.process_edge(High, None, at(100)), Some(High));
+        for _ in 1..stable_ticks {
+            assert_eq!(debouncer.process_debounce_tick(High, TICK), None);
+            assert!(!debouncer.is_stable());
+        }
+        assert_eq!(debouncer.process_debounce_tick(High, TICK), None);
+        assert_eq!(debouncer.stable_level(), Some(High));
+    }
+
+    #[test]
+    fn tick_after_settle() {
+        let config = DebounceConfig::REED;
+        let mut debouncer = Debouncer::new(config);
+        for _ in 0..=config.stable_ticks(TICK) {
+            assert_eq!(debouncer.process_debounce_tick(High, TICK), None);
+        }
+        assert_eq!(debouncer.process_edge(Low, None, at(100)), None);
+        assert_eq!(debouncer.process_debounce_tick(Low, TICK), Some(Low));
+        assert_eq!(debouncer.stable_level(), Some(Low));
+    }
+
+    #[test]
+    fn tick_revalidates_a_missed_edge() {
+        let config = DebounceConfig::REED;
+        let mut debouncer = Debouncer::new(config);
+        for _ in 0..=config.stable_ticks(TICK) {
+            assert_eq!(debouncer.process_debounce_tick(Low, TICK), None);
+        }
+        assert_eq!(debouncer.process_debounce_tick(High, TICK), None);
+        assert_eq!(debouncer.state(), InputSignalState::StabilizingHigh);
+        assert_eq!(debouncer.process_debounce_tick(High, TICK), Some(High));
+    }
+
+    #[test]
+    fn single_edge_sense_goes_by_the_sample_after_a_missed_edge() {
+        let mut debouncer = stable_at(DebounceConfig::MECHANICAL, Low);
+        assert_eq!(
+            debouncer.process_edge(High, Some(High), at(100)),
+            Some(High)
+        );
+        assert_eq!(debouncer.process_settle_deadline(High, at(150)), None);
+        //The falling edge raised no interrupt, the next rising one doesn't
+        //mean the input went low
+        assert_eq!(debouncer.process_edge(High, Some(High), at(300)), None);
+        assert_eq!(debouncer.stable_level(), Some(High));
+        //Polling picks up the input being low
+        assert_eq!(debouncer.process_settle_deadline(Low, at(400)), None);
+        assert_eq!(debouncer.process_settle_deadline(Low, at(450)), Some(Low));
+    }
+
+    #[test]
+    fn bounces_past_the_maximum_flag_noisy() {
+        let mut debouncer = stable_at(DebounceConfig::CLEAN, Low);
+        debouncer.process_edge(High, None, at(100));
+        debouncer.process_edge(Low, None, at(100));
+        assert!(!debouncer.take_noisy());
+        debouncer.process_edge(High, None, at(100));
+        assert!(debouncer.take_noisy());
+        assert!(!debouncer.take_noisy());
+    }
+
+    /// xorshift64, deterministic so a failing sequence can be replayed
+    struct Random(u64);
+
+    impl Random {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn chance(&mut self, one_in: u64) -> bool {
+            self.next().is_multiple_of(one_in)
+        }
+    }
+
+    /// Reported levels, the debouncer after the input stayed at its final
+    /// level long enough and that level
+    struct Run {
+        reports: Vec<LogicState>,
+        debouncer: Debouncer,
+        level: LogicState,
+    }
+
+    /// Drives a debouncer with random edges, deadlines and ticks, then leaves
+    /// the input alone until it settles.  `sense` picks the level edges move
+    /// to like `input_signal` does, None for both edges.
+    fn run(random: &mut Random, config: DebounceConfig, sense: Option<LogicState>) -> Run {
+        let mut debouncer = Debouncer::new(config);
+        let mut reports = Vec::new();
+        let mut is_high = random.chance(2);
+        let mut now = Instant::ZERO;
+        for _ in 0..random.next() % 64 {
+            now += Duration::from_micros(random.next() % 100_000);
+            let report = match random.next() % 3 {
+                0 => {
+                    is_high = !is_high;
+                    let level = LogicState::from(is_high);
+                    //Edges of a single edge sense only interrupt one way
+                    match sense {
+                        Some(moved_to) if moved_to != level => None,
+                        _ => debouncer.process_edge(level, sense, now),
+                    }
+                }
+                1 => debouncer.process_settle_deadline(LogicState::from(is_high), now),
+                _ => debouncer.process_debounce_tick(LogicState::from(is_high), TICK),
+            };
+            reports.extend(report);
+        }
+        let level = LogicState::from(is_high);
+        for _ in 0..=config.stable_ticks(TICK) as u32 * 2 {
+            now += TICK;
+            reports.extend(debouncer.process_settle_deadline(level, now));
+            reports.extend(debouncer.process_debounce_tick(level, TICK));
+        }
+        Run {
+            reports,
+            debouncer,
+            level,
+        }
+    }
+
+    const CONFIGS: [DebounceConfig; 3] = [
+        DebounceConfig::MECHANICAL,
+        DebounceConfig::REED,
+        DebounceConfig::CLEAN,
+    ];
+    const SENSES: [Option<LogicState>; 3] = [None, Some(High), Some(Low)];
+    const RUNS: usize = 2000;
+
+    #[test]
+    fn reported_levels_alternate() {
+        let mut random = Random(0x1234_5678_9ABC_DEF0);
+        for config in CONFIGS {
+            for sense in SENSES {
+                for _ in 0..RUNS {
+                    let run = run(&mut random, config, sense);
+                    for pair in run.reports.windows(2) {
+                        assert_ne!(pair[0], pair[1], "{:?}", run.reports);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn final_level_matches_sampled_level() {
+        let mut random = Random(0x0FED_CBA9_8765_4321);
+        for config in CONFIGS {
+            for sense in SENSES {
+                for _ in 0..RUNS {
+                    let run = run(&mut random, config, sense);
+                    assert_eq!(run.debouncer.stable_level(), Some(run.level));
+                    assert_eq!(run.debouncer.level(), run.level);
+                    if let Some(&last) = run.reports.last() {
+                        assert_eq!(last, run.level, "{:?}", run.reports);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -1,409 +1,314 @@
-use core::ptr;
+use core::{
+    ptr,
+    sync::atomic::{AtomicU8, Ordering},
+};
 
-use crate::println;
+use crate::{
+    interrupt_stats::{self, Source},
+    interrupt_storm,
+    plic::{contexts, Context, ExecutionMode, HartId, HartMask, Plic, NUMBER_INTERRUPTS},
+    println,
+};
 use jh7110_pac::{self as pac};
 
+pub use crate::plic::InterruptPriority;
+
 #[riscv_rt::core_interrupt(riscv::interrupt::Interrupt::MachineExternal)]
 fn machine_external_isr() {
-    //TODO Maybe move this external and thread safe with a mutex to ensure thread safty
-    //when multipal cores are running.  Not sure if I need to accunt for interrupt
-    //priorities or not
-    //Claim the interrupt
-    let hart_id = HartId::from(riscv::register::mhartid::read());
-    let interrupt_number = plic_claim(&hart_id, ExecutionMode::Machine);
+    //Claim the interrupt from the context of the hart that took the trap
+    let plic = Plic::new();
+    let context = Context::current_machine();
+    let interrupt_number = plic.claim(context);
     //println!("Global interrupt number: {}", interrupt_number);
-    if interrupt_number != 0 {
-        //Load irs entry from the table
-        let v: &pac::Vector = &pac::__EXTERNAL_INTERRUPTS[interrupt_number as usize];
-        unsafe {
-            //Check if its set and execute handler
-            if v._reserved != 0 {
-                (v._handler)();
-            } else {
-                println!("reserved interrupt hit. wait what?");
-            }
-        }
-        //Complete the interrupt
-        plic_complete(&hart_id, ExecutionMode::Machine, interrupt_number);
+    if interrupt_number == 0 {
+        //Nothing pending, another hart may have claimed it first
+        interrupt_storm::record_spurious();
+        return;
     }
+    //Sources firing faster than the storm limit get masked instead of handled
+    if interrupt_storm::track_claim(interrupt_number) {
+        let start = interrupt_stats::start();
+        #[cfg(not(feature = "nested-interrupts"))]
+        dispatch(interrupt_number);
+        #[cfg(feature = "nested-interrupts")]
+        dispatch_nested(&plic, context, interrupt_number);
+        interrupt_stats::record(Source::External(interrupt_number), start);
+    }
+    //Complete the interrupt
+    plic.complete(context, interrupt_number);
 }
 
-#[repr(u8)]
-#[derive(Debug)]
-pub enum InterruptPriority {
-    Disabled = 0,
-    Priority1 = 1,
-    Priority2 = 2,
-    Priority3 = 3,
-    Priority4 = 4,
-    Priority5 = 5,
-    Priority6 = 6,
-    Priority7 = 7,
+/// Type erased handler registered for an interrupt source.  `trampoline` is
+/// monomorphized for the handler's context type and casts `handler` and
+/// `context` back before calling it.
+#[derive(Clone, Copy)]
+struct HandlerEntry {
+    trampoline: unsafe fn(*const (), *mut ()),
+    handler: *const (),
+    context: *mut (),
 }
 
-impl From<u32> for InterruptPriority {
-    fn from(value: u32) -> Self {
-        match value {
-            0 => InterruptPriority::Disabled,
-            1 => InterruptPriority::Priority1,
-            2 => InterruptPriority::Priority2,
-            3 => InterruptPriority::Priority3,
-            4 => InterruptPriority::Priority4,
-            5 => InterruptPriority::Priority5,
-            6 => InterruptPriority::Priority6,
-            7 => InterruptPriority::Priority7,
-            _ => InterruptPriority::Disabled,
-        }
-    }
+// The context pointer is only dereferenced by the handler the registering
+// driver handed over together with it.
+unsafe impl Send for HandlerEntry {}
+
+unsafe fn call_with_context<Ctx>(handler: *const (), context: *mut ()) {
+    let handler: fn(&mut Ctx) = core::mem::transmute(handler);
+    handler(&mut *(context as *mut Ctx));
+}
+
+unsafe fn call_without_context(handler: *const (), _context: *mut ()) {
+    let handler: fn() = core::mem::transmute(handler);
+    handler();
 }
 
-#[repr(u8)]
+/// Dispatch table consulted by [machine_external_isr], indexed by interrupt
+/// number.
+static HANDLERS: spin::Mutex<[Option<HandlerEntry>; NUMBER_INTERRUPTS]> =
+    spin::Mutex::new([None; NUMBER_INTERRUPTS]);
+
 #[derive(Debug)]
-enum ExecutionMode {
-    Machine,
-    Supervisor,
+pub enum HandlerError {
+    /// A handler is already registered for the interrupt source
+    AlreadyRegistered,
 }
 
-#[repr(u8)]
-#[derive(Debug, Clone)]
-enum HartId {
-    Hart0,
-    Hart1,
-    Hart2,
-    Hart3,
-    Hart4,
+/// Registers `handler` to be called with `context` whenever the interrupt
+/// source is claimed.
+pub fn register_handler<Ctx>(
+    interrupt: pac::Interrupt,
+    handler: fn(&mut Ctx),
+    context: &'static mut Ctx,
+) -> Result<(), HandlerError> {
+    // An exclusive 'static borrow is never used by anyone but the handler
+    unsafe { register_handler_ptr(interrupt, handler, context) }
 }
 
-impl From<usize> for HartId {
-    fn from(value: usize) -> Self {
-        match value {
-            0 => HartId::Hart0,
-            1 => HartId::Hart1,
-            2 => HartId::Hart2,
-            3 => HartId::Hart3,
-            4 => HartId::Hart4,
-            _ => HartId::Hart0,
-        }
-    }
+/// Registers `handler` to be called with `context` whenever the interrupt
+/// source is claimed.
+///
+/// # Safety
+///
+/// `context` must stay valid until the handler is unregistered.  When the same
+/// context is registered for several sources those handlers must not preempt
+/// each other, i.e. they must share a priority and a hart.
+pub unsafe fn register_handler_ptr<Ctx>(
+    interrupt: pac::Interrupt,
+    handler: fn(&mut Ctx),
+    context: *mut Ctx,
+) -> Result<(), HandlerError> {
+    insert_handler(
+        interrupt,
+        HandlerEntry {
+            trampoline: call_with_context::<Ctx>,
+            handler: handler as *const (),
+            context: context as *mut (),
+        },
+    )
 }
 
-pub fn enable_interrupt(interrupt_number: pac::Interrupt, priority: InterruptPriority) {
-    plic_set_interrupt_priority(interrupt_number, priority);
-    //NOTE:  Pending bit can be cleared by enabeling the interrupt and then claiming it
+/// Registers a handler that does not carry any context.
+pub fn register_handler_fn(interrupt: pac::Interrupt, handler: fn()) -> Result<(), HandlerError> {
+    insert_handler(
+        interrupt,
+        HandlerEntry {
+            trampoline: call_without_context,
+            handler: handler as *const (),
+            context: ptr::null_mut(),
+        },
+    )
+}
 
-    //Enable the interrupt
-    plic_enable_interrupt(HartId::Hart1, ExecutionMode::Machine, interrupt_number);
+fn insert_handler(interrupt: pac::Interrupt, entry: HandlerEntry) -> Result<(), HandlerError> {
+    //Keep the interrupts on this hart off while holding the lock, otherwise
+    //machine_external_isr could spin on it forever
+    riscv::interrupt::free(|| {
+        let mut handlers = HANDLERS.lock();
+        let slot = &mut handlers[interrupt as usize];
+        match slot {
+            Some(_) => Err(HandlerError::AlreadyRegistered),
+            None => {
+                *slot = Some(entry);
+                Ok(())
+            }
+        }
+    })
+}
 
-    //Set the prority threshold (for now just or it with the prority value so at least
-    //this one will fire)
-    plic_set_interrupt_priority_threshold(HartId::Hart1, ExecutionMode::Machine, 1)
+/// Removes the handler of the interrupt source.  The source stays enabled, if
+/// it fires again it ends up in [default_handler] which disables it.
+pub fn unregister_handler(interrupt: pac::Interrupt) {
+    riscv::interrupt::free(|| {
+        HANDLERS.lock()[interrupt as usize] = None;
+    });
 }
 
-//PLIC base address
-const PLIC_BASE: u32 = 0x0C00_0000;
-
-//Priority (offset into this by interrupt_number * 4)
-const PLIC_PRIORITY: u32 = PLIC_BASE;
-
-//Interrupt Pending
-//Register offset address = (interrupt_number/32) * 4
-//Bit offset = interrupt_number % 32
-const PLIC_PENDING: u32 = PLIC_BASE + 0x1000;
-
-//Enterrupt enables by hart and mode
-//Register offset address = (interrupt_number/32) * 4
-//Bit offset = interrupt_number % 32
-const PLIC_HART0_MMODE_ENABLES: u32 = PLIC_BASE + 0x2000;
-//Hart 1
-const PLIC_HART1_MMODE_ENABLES: u32 = PLIC_BASE + 0x2080;
-const PLIC_HART1_SMODE_ENABLES: u32 = PLIC_BASE + 0x2100;
-//Hart 2
-const PLIC_HART2_MMODE_ENABLES: u32 = PLIC_BASE + 0x2180;
-const PLIC_HART2_SMODE_ENABLES: u32 = PLIC_BASE + 0x2200;
-//Hart 3
-const PLIC_HART3_MMODE_ENABLES: u32 = PLIC_BASE + 0x2280;
-const PLIC_HART3_SMODE_ENABLES: u32 = PLIC_BASE + 0x2300;
-//Hart 4
-const PLIC_HART4_MMODE_ENABLES: u32 = PLIC_BASE + 0x2380;
-const PLIC_HART4_SMODE_ENABLES: u32 = PLIC_BASE + 0x2400;
-
-//Priority/Claim registers by hart and mode
-//Hart0
-const PILC_HART0_MMODE_PRIORITY_THRESHOLD: u32 = PLIC_BASE + 0x20_0000;
-const PILC_HART0_MMODE_CLAIM_COMPLETE: u32 = PLIC_BASE + 0x20_0004;
-//Hart1
-const PILC_HART1_MMODE_PRIORITY_THRESHOLD: u32 = PLIC_BASE + 0x20_1000;
-const PILC_HART1_MMODE_CLAIM_COMPLETE: u32 = PLIC_BASE + 0x20_1004;
-const PILC_HART1_SMODE_PRIORITY_THRESHOLD: u32 = PLIC_BASE + 0x20_2000;
-const PILC_HART1_SMODE_CLAIM_COMPLETE: u32 = PLIC_BASE + 0x20_2004;
-//Hart2
-const PILC_HART2_MMODE_PRIORITY_THRESHOLD: u32 = PLIC_BASE + 0x20_3000;
-const PILC_HART2_MMODE_CLAIM_COMPLETE: u32 = PLIC_BASE + 0x20_3004;
-const PILC_HART2_SMODE_PRIORITY_THRESHOLD: u32 = PLIC_BASE + 0x20_4000;
-const PILC_HART2_SMODE_CLAIM_COMPLETE: u32 = PLIC_BASE + 0x20_4004;
-//Hart3
-const PILC_HART3_MMODE_PRIORITY_THRESHOLD: u32 = PLIC_BASE + 0x20_5000;
-const PILC_HART3_MMODE_CLAIM_COMPLETE: u32 = PLIC_BASE + 0x20_5004;
-const PILC_HART3_SMODE_PRIORITY_THRESHOLD: u32 = PLIC_BASE + 0x20_6000;
-const PILC_HART3_SMODE_CLAIM_COMPLETE: u32 = PLIC_BASE + 0x20_6004;
-//Hart4
-const PILC_HART4_MMODE_PRIORITY_THRESHOLD: u32 = PLIC_BASE + 0x20_7000;
-const PILC_HART4_MMODE_CLAIM_COMPLETE: u32 = PLIC_BASE + 0x20_7004;
-const PILC_HART4_SMODE_PRIORITY_THRESHOLD: u32 = PLIC_BASE + 0x20_8000;
-const PILC_HART4_SMODE_CLAIM_COMPLETE: u32 = PLIC_BASE + 0x20_8004;
+/// Looks up the registered handler of the claimed interrupt
+fn handler_entry(interrupt_number: u32) -> Option<HandlerEntry> {
+    //Copy the entry out so the lock isn't held while the handler runs
+    HANDLERS
+        .lock()
+        .get(interrupt_number as usize)
+        .copied()
+        .flatten()
+}
 
-pub fn clear_interrupt_enable_all() {
-    for i in 1..5 {
-        unsafe {
-            ptr::write_volatile((PLIC_HART0_MMODE_ENABLES + 4 * i) as *mut u32, 0);
-            ptr::write_volatile((PLIC_HART1_MMODE_ENABLES + 4 * i) as *mut u32, 0);
-            ptr::write_volatile((PLIC_HART1_SMODE_ENABLES + 4 * i) as *mut u32, 0);
-            ptr::write_volatile((PLIC_HART2_MMODE_ENABLES + 4 * i) as *mut u32, 0);
-            ptr::write_volatile((PLIC_HART2_SMODE_ENABLES + 4 * i) as *mut u32, 0);
-            ptr::write_volatile((PLIC_HART3_MMODE_ENABLES + 4 * i) as *mut u32, 0);
-            ptr::write_volatile((PLIC_HART3_SMODE_ENABLES + 4 * i) as *mut u32, 0);
-            ptr::write_volatile((PLIC_HART4_MMODE_ENABLES + 4 * i) as *mut u32, 0);
-            ptr::write_volatile((PLIC_HART4_SMODE_ENABLES + 4 * i) as *mut u32, 0);
-        }
+/// Runs the handler, falling back to [default_handler].
+fn run_handler(entry: Option<HandlerEntry>, interrupt_number: u32) {
+    match entry {
+        Some(entry) => unsafe { (entry.trampoline)(entry.handler, entry.context) },
+        None => default_handler(interrupt_number),
     }
 }
 
-pub fn clear_interrupt_priotiry_all() {
-    for i in 1..137 {
-        unsafe {
-            ptr::write_volatile((PLIC_PRIORITY + i * 4) as *mut u32, 0);
-        }
-    }
+/// Runs the registered handler of the claimed interrupt, falling back to
+/// [default_handler].
+#[cfg(not(feature = "nested-interrupts"))]
+fn dispatch(interrupt_number: u32) {
+    run_handler(handler_entry(interrupt_number), interrupt_number);
 }
-pub fn print_interrupt_enable() {
-    println!("Interrupt Enable");
+
+/// Runs the registered handler of the claimed interrupt with interrupts enabled
+/// so a source with a higher [InterruptPriority] can preempt it.
+///
+/// The context threshold is raised to the priority of the claimed source, so
+/// only strictly higher priorities get through.  `mepc` and `mstatus` are saved
+/// because a nested trap overwrites them, and everything is restored before the
+/// caller completes the interrupt.
+///
+/// Handlers that may be preempted must not hold a lock a higher priority
+/// handler takes as well (`println!` included), that would spin forever.
+#[cfg(feature = "nested-interrupts")]
+fn dispatch_nested(plic: &Plic, context: Context, interrupt_number: u32) {
+    use riscv::register::{mepc, mstatus};
+
+    //Take the table lock before interrupts are back on
+    let entry = handler_entry(interrupt_number);
+
+    let threshold = plic.threshold(context);
+    plic.set_threshold(context, plic.priority(interrupt_number));
+    let saved_mepc = mepc::read();
+    let saved_mstatus = mstatus::read();
     unsafe {
-        for i in 1..5 {
-            let mut val = ptr::read_volatile((PLIC_HART0_MMODE_ENABLES + 4 * i) as *const u32);
-            println!("Hart: {}, RegNum: {}, Value: {:#10x}", 0, i, val);
-            val = ptr::read_volatile((PLIC_HART1_MMODE_ENABLES + 4 * i) as *const u32);
-            println!("Hart: {}, RegNum: {}, Value: {:#10x}", 1, i, val);
-            val = ptr::read_volatile((PLIC_HART1_SMODE_ENABLES + 4 * i) as *const u32);
-            println!("Hart: {}, RegNum: {}, Value: {:#10x}", 1, i, val);
-            val = ptr::read_volatile((PLIC_HART2_MMODE_ENABLES + 4 * i) as *const u32);
-            println!("Hart: {}, RegNum: {}, Value: {:#10x}", 2, i, val);
-            val = ptr::read_volatile((PLIC_HART2_SMODE_ENABLES + 4 * i) as *const u32);
-            println!("Hart: {}, RegNum: {}, Value: {:#10x}", 2, i, val);
-            val = ptr::read_volatile((PLIC_HART3_MMODE_ENABLES + 4 * i) as *const u32);
-            println!("Hart: {}, RegNum: {}, Value: {:#10x}", 3, i, val);
-            val = ptr::read_volatile((PLIC_HART3_SMODE_ENABLES + 4 * i) as *const u32);
-            println!("Hart: {}, RegNum: {}, Value: {:#10x}", 3, i, val);
-            val = ptr::read_volatile((PLIC_HART4_MMODE_ENABLES + 4 * i) as *const u32);
-            println!("Hart: {}, RegNum: {}, Value: {:#10x}", 4, i, val);
-            val = ptr::read_volatile((PLIC_HART4_SMODE_ENABLES + 4 * i) as *const u32);
-            println!("Hart: {}, RegNum: {}, Value: {:#10x}", 4, i, val);
-        }
+        mstatus::set_mie();
     }
-}
 
-pub fn print_priority_interrupt_info() {
-    for i in 1..137 {
-        unsafe {
-            let val = ptr::read_volatile((PLIC_PRIORITY + i * 4) as *const u32);
-            println!("Interrupt: {}, Priority: {}", i, val);
+    run_handler(entry, interrupt_number);
+
+    unsafe {
+        mstatus::clear_mie();
+        mepc::write(saved_mepc);
+        //MPIE is always set when an interrupt was taken, a nested trap only
+        //leaves MPP pointing at machine mode
+        if saved_mstatus.mpie() {
+            mstatus::set_mpie();
         }
+        mstatus::set_mpp(saved_mstatus.mpp());
     }
+    plic.set_threshold(context, threshold);
 }
 
-fn plic_set_interrupt_priority(interrupt_number: pac::Interrupt, priority: InterruptPriority) {
-    let priority_register = PLIC_PRIORITY + 4 * interrupt_number as u32;
-    unsafe {
-        ptr::write_volatile(priority_register as *mut u32, priority as u32);
+/// Enables the interrupt source on hart 1 in machine mode.
+pub fn enable_interrupt(interrupt_number: pac::Interrupt, priority: InterruptPriority) {
+    if let Err(skipped) = enable_interrupt_on(
+        interrupt_number,
+        priority,
+        HartMask::HART1,
+        ExecutionMode::Machine,
+    ) {
+        println!("{:?} not enabled on {:?}", interrupt_number, skipped);
     }
 }
 
-fn plic_enable_interrupt(
-    hart: HartId,
-    execution_mode: ExecutionMode,
+/// Enables the interrupt source on every hart in `harts` for the execution
+/// mode.  Only harts that ran [init_hart_context] can be targeted, on the
+/// others the source would stay masked by the reset threshold and never fire.
+/// Those and harts without a context for the mode (hart 0 has no supervisor
+/// mode) are skipped and returned as the error.
+///
+/// The priority threshold of the contexts is left alone, it is set up once per
+/// hart by [init_hart_context].
+pub fn enable_interrupt_on(
     interrupt_number: pac::Interrupt,
-) {
-    //Register offset address = (interrupt_number/32) * 4
-    //Bit offset = interrupt_number % 32
-    //
-    let register_offset = (interrupt_number as u32 / 32) * 4;
-    let bit_offset = interrupt_number as u32 % 32;
-    let base = match hart {
-        HartId::Hart0 => PLIC_HART0_MMODE_ENABLES,
-        HartId::Hart1 => match execution_mode {
-            ExecutionMode::Machine => PLIC_HART1_MMODE_ENABLES,
-            ExecutionMode::Supervisor => PLIC_HART1_SMODE_ENABLES,
-        },
-        HartId::Hart2 => match execution_mode {
-            ExecutionMode::Machine => PLIC_HART2_MMODE_ENABLES,
-            ExecutionMode::Supervisor => PLIC_HART2_SMODE_ENABLES,
-        },
-        HartId::Hart3 => match execution_mode {
-            ExecutionMode::Machine => PLIC_HART3_MMODE_ENABLES,
-            ExecutionMode::Supervisor => PLIC_HART3_SMODE_ENABLES,
-        },
-        HartId::Hart4 => match execution_mode {
-            ExecutionMode::Machine => PLIC_HART4_MMODE_ENABLES,
-            ExecutionMode::Supervisor => PLIC_HART4_SMODE_ENABLES,
-        },
-    };
-    set_bit(base + register_offset, bit_offset);
+    priority: InterruptPriority,
+    harts: HartMask,
+    mode: ExecutionMode,
+) -> Result<(), HartMask> {
+    let plic = Plic::new();
+    plic.set_priority(interrupt_number, priority);
+    //NOTE:  Pending bit can be cleared by enabeling the interrupt and then claiming it
+
+    //Enable the interrupt
+    let mut skipped = harts.difference(ready_harts());
+    for hart in harts.intersection(ready_harts()).harts() {
+        match Context::new(hart, mode) {
+            Some(context) => plic.enable(context, interrupt_number),
+            None => skipped |= HartMask::from(hart),
+        }
+    }
+    match skipped.is_empty() {
+        true => Ok(()),
+        false => Err(skipped),
+    }
 }
 
-fn plic_set_interrupt_priority_threshold(
-    hart: HartId,
-    execution_mode: ExecutionMode,
-    threshold: u32,
+/// Disables the interrupt source on every hart in `harts` for the execution
+/// mode.
+pub fn disable_interrupt_on(
+    interrupt_number: pac::Interrupt,
+    harts: HartMask,
+    mode: ExecutionMode,
 ) {
-    let reg = match hart {
-        HartId::Hart0 => PILC_HART0_MMODE_PRIORITY_THRESHOLD,
-        HartId::Hart1 => match execution_mode {
-            ExecutionMode::Machine => PILC_HART1_MMODE_PRIORITY_THRESHOLD,
-            ExecutionMode::Supervisor => PILC_HART1_SMODE_PRIORITY_THRESHOLD,
-        },
-        HartId::Hart2 => match execution_mode {
-            ExecutionMode::Machine => PILC_HART2_MMODE_PRIORITY_THRESHOLD,
-            ExecutionMode::Supervisor => PILC_HART2_SMODE_PRIORITY_THRESHOLD,
-        },
-        HartId::Hart3 => match execution_mode {
-            ExecutionMode::Machine => PILC_HART3_MMODE_PRIORITY_THRESHOLD,
-            ExecutionMode::Supervisor => PILC_HART3_SMODE_PRIORITY_THRESHOLD,
-        },
-        HartId::Hart4 => match execution_mode {
-            ExecutionMode::Machine => PILC_HART4_MMODE_PRIORITY_THRESHOLD,
-            ExecutionMode::Supervisor => PILC_HART4_SMODE_PRIORITY_THRESHOLD,
-        },
-    };
-    unsafe {
-        ptr::write_volatile(reg as *mut u32, threshold & 0b111);
+    let plic = Plic::new();
+    for hart in harts.harts() {
+        if let Some(context) = Context::new(hart, mode) {
+            plic.disable(context, interrupt_number);
+        }
     }
 }
 
-fn plic_claim(hart: &HartId, execution_mode: ExecutionMode) -> u32 {
-    let reg = match hart {
-        HartId::Hart0 => PILC_HART0_MMODE_CLAIM_COMPLETE,
-        HartId::Hart1 => match execution_mode {
-            ExecutionMode::Machine => PILC_HART1_MMODE_CLAIM_COMPLETE,
-            ExecutionMode::Supervisor => PILC_HART1_SMODE_CLAIM_COMPLETE,
-        },
-        HartId::Hart2 => match execution_mode {
-            ExecutionMode::Machine => PILC_HART2_MMODE_CLAIM_COMPLETE,
-            ExecutionMode::Supervisor => PILC_HART2_SMODE_CLAIM_COMPLETE,
-        },
-        HartId::Hart3 => match execution_mode {
-            ExecutionMode::Machine => PILC_HART3_MMODE_CLAIM_COMPLETE,
-            ExecutionMode::Supervisor => PILC_HART3_SMODE_CLAIM_COMPLETE,
-        },
-        HartId::Hart4 => match execution_mode {
-            ExecutionMode::Machine => PILC_HART4_MMODE_CLAIM_COMPLETE,
-            ExecutionMode::Supervisor => PILC_HART4_SMODE_CLAIM_COMPLETE,
-        },
-    };
-    unsafe { ptr::read_volatile(reg as *const u32) }
+/// Harts that ran [init_hart_context], as [HartMask] bits
+static READY_HARTS: AtomicU8 = AtomicU8::new(0);
+
+/// Sets up the machine mode context of the hart executing this code so every
+/// enabled source with a non-zero priority can interrupt it.  Must be called
+/// once on each hart that takes external interrupts.
+pub fn init_hart_context() {
+    let plic = Plic::new();
+    plic.set_threshold(Context::current_machine(), InterruptPriority::Disabled);
+    READY_HARTS.fetch_or(HartMask::from(HartId::current()).bits(), Ordering::Release);
 }
 
-fn plic_complete(hart: &HartId, execution_mode: ExecutionMode, interrupt_number: u32) {
-    let reg = match hart {
-        HartId::Hart0 => PILC_HART0_MMODE_CLAIM_COMPLETE,
-        HartId::Hart1 => match execution_mode {
-            ExecutionMode::Machine => PILC_HART1_MMODE_CLAIM_COMPLETE,
-            ExecutionMode::Supervisor => PILC_HART1_SMODE_CLAIM_COMPLETE,
-        },
-        HartId::Hart2 => match execution_mode {
-            ExecutionMode::Machine => PILC_HART2_MMODE_CLAIM_COMPLETE,
-            ExecutionMode::Supervisor => PILC_HART2_SMODE_CLAIM_COMPLETE,
-        },
-        HartId::Hart3 => match execution_mode {
-            ExecutionMode::Machine => PILC_HART3_MMODE_CLAIM_COMPLETE,
-            ExecutionMode::Supervisor => PILC_HART3_SMODE_CLAIM_COMPLETE,
-        },
-        HartId::Hart4 => match execution_mode {
-            ExecutionMode::Machine => PILC_HART4_MMODE_CLAIM_COMPLETE,
-            ExecutionMode::Supervisor => PILC_HART4_SMODE_CLAIM_COMPLETE,
-        },
-    };
-    unsafe {
-        ptr::write_volatile(reg as *mut u32, interrupt_number);
-    }
+/// Harts that ran [init_hart_context] and can be targeted by
+/// [enable_interrupt_on]
+pub fn ready_harts() -> HartMask {
+    HartMask::from_bits_truncate(READY_HARTS.load(Ordering::Acquire))
 }
 
-fn set_bit(reg: u32, bit: u32) {
-    unsafe {
-        let regval = ptr::read_volatile(reg as *const u32);
-        ptr::write_volatile(reg as *mut u32, regval | 1 << bit);
+pub fn clear_interrupt_enable_all() {
+    let plic = Plic::new();
+    for (_, _, context) in contexts() {
+        plic.disable_all(context);
     }
 }
 
-pub fn print_pending_interrupt_info() {
-    for i in 1..137 {
-        let reg_offset: u32 = 4 * (i / 32);
-        let bit_mask: u32 = 1 << (i % 32);
-        unsafe {
-            let val = ptr::read_volatile((PLIC_PENDING + reg_offset) as *const u32);
-            let set = (val & bit_mask) > 0;
-            println!("Interrupt: {}, Pending: {}", i, set);
-        }
+pub fn clear_interrupt_priotiry_all() {
+    let plic = Plic::new();
+    for i in 1..NUMBER_INTERRUPTS as u32 {
+        plic.set_priority_number(i, InterruptPriority::Disabled);
     }
 }
 
-#[no_mangle]
-fn default_handler() {
-    // This function will handle any unassigned interrupts
-    println!("Default interrupt handler");
+/// Handles any interrupt source that does not have a registered handler.  The
+/// source is disabled on every context so it can't keep firing.
+fn default_handler(interrupt_number: u32) {
+    println!("Default interrupt handler: {}", interrupt_number);
+    interrupt_storm::record_spurious();
+    if interrupt_number as usize >= NUMBER_INTERRUPTS {
+        return;
+    }
+    let plic = Plic::new();
+    for (_, _, context) in contexts() {
+        plic.disable_number(context, interrupt_number);
+    }
 }
-
-pac::interrupt!(L2PM, default_handler);
-pac::interrupt!(L2PM1, default_handler);
-pac::interrupt!(L2PM2, default_handler);
-pac::interrupt!(L2PM3, default_handler);
-pac::interrupt!(ETH_LPI0, default_handler);
-pac::interrupt!(ETH_WAKE_IRQ0, default_handler);
-pac::interrupt!(MACIRQ0, default_handler);
-pac::interrupt!(QSPI0, default_handler);
-pac::interrupt!(CRYPTO, default_handler);
-pac::interrupt!(SDMA, default_handler);
-pac::interrupt!(TRNG, default_handler);
-pac::interrupt!(UART0, default_handler); // Assuming you want a specific handler for UART0
-pac::interrupt!(UART1, default_handler);
-pac::interrupt!(UART2, default_handler);
-pac::interrupt!(I2C0, default_handler);
-pac::interrupt!(I2C1, default_handler);
-pac::interrupt!(I2C2, default_handler);
-pac::interrupt!(SPI0, default_handler);
-pac::interrupt!(SPI1, default_handler);
-pac::interrupt!(SPI2, default_handler);
-pac::interrupt!(UART3, default_handler);
-pac::interrupt!(UART4, default_handler);
-pac::interrupt!(UART5, default_handler);
-pac::interrupt!(I2C3, default_handler);
-pac::interrupt!(I2C4, default_handler);
-pac::interrupt!(I2C5, default_handler);
-pac::interrupt!(I2C6, default_handler);
-pac::interrupt!(SPI3, default_handler);
-pac::interrupt!(SPI4, default_handler);
-pac::interrupt!(SPI5, default_handler);
-pac::interrupt!(SPI6, default_handler);
-//pac::interrupt!(PTC0, default_handler);
-//pac::interrupt!(PTC1, default_handler);
-pac::interrupt!(PTC2, default_handler);
-pac::interrupt!(PTC3, default_handler);
-pac::interrupt!(PTC4, default_handler);
-pac::interrupt!(PTC5, default_handler);
-pac::interrupt!(PTC6, default_handler);
-pac::interrupt!(PTC7, default_handler);
-pac::interrupt!(WDOG, default_handler);
-//pac::interrupt!(TIMER0, default_handler);
-pac::interrupt!(TIMER1, default_handler);
-pac::interrupt!(TIMER2, default_handler);
-pac::interrupt!(TIMER3, default_handler);
-pac::interrupt!(DMA, default_handler);
-pac::interrupt!(MMC0, default_handler);
-pac::interrupt!(MMC1, default_handler);
-pac::interrupt!(ETH_LPI1, default_handler);
-pac::interrupt!(ETH_WAKE_IRQ1, default_handler);
-pac::interrupt!(MACIRQ1, default_handler);
-pac::interrupt!(AON_IOMUX, default_handler);
-//pac::interrupt!(SYS_IOMUX, default_handler);
-pac::interrupt!(HOST0, default_handler);
-pac::interrupt!(PERIPHERAL0, default_handler);
-pac::interrupt!(OTG0, default_handler);
-pac::interrupt!(PMU, default_handler);
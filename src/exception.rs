@@ -0,0 +1,206 @@
+//! Decoded exception reports.
+//!
+//! `ExceptionHandler` names the exception from `mcause`, prints the trap CSRs,
+//! the faulting instruction and a frame pointer backtrace, then follows the
+//! configured [ExceptionPolicy].  The backtrace addresses can be looked up in
+//! `vf2-riscv-rt.objdmp`, it relies on the code being built with frame pointers
+//! (see `.cargo/config.toml`).
+//!
+//! The crash record is written first and the report goes through
+//! [log::print_force], like the panic handler's, so a fault while the logger
+//! is locked still gets recorded and printed.
+use core::{
+    ptr,
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+use riscv::register::{mcause, mepc, mstatus, mtval};
+
+use crate::{crash, ipi, log, plic::HartId, watchdog};
+
+/// Start and end of the SRAM the stacks live in, a frame pointer outside of it
+/// ends the backtrace
+const STACK_LOW: usize = 0x0800_0000;
+const STACK_HIGH: usize = 0x0803_1000;
+/// Maximum number of frames printed
+const MAX_FRAMES: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exception {
+    InstructionMisaligned,
+    InstructionFault,
+    IllegalInstruction,
+    Breakpoint,
+    LoadMisaligned,
+    LoadFault,
+    StoreMisaligned,
+    StoreFault,
+    UserEnvCall,
+    SupervisorEnvCall,
+    MachineEnvCall,
+    InstructionPageFault,
+    LoadPageFault,
+    StorePageFault,
+    Unknown(usize),
+}
+
+impl From<usize> for Exception {
+    fn from(code: usize) -> Self {
+        match code {
+            0 => Exception::InstructionMisaligned,
+            1 => Exception::InstructionFault,
+            2 => Exception::IllegalInstruction,
+            3 => Exception::Breakpoint,
+            4 => Exception::LoadMisaligned,
+            5 => Exception::LoadFault,
+            6 => Exception::StoreMisaligned,
+            7 => Exception::StoreFault,
+            8 => Exception::UserEnvCall,
+            9 => Exception::SupervisorEnvCall,
+            11 => Exception::MachineEnvCall,
+            12 => Exception::InstructionPageFault,
+            13 => Exception::LoadPageFault,
+            15 => Exception::StorePageFault,
+            code => Exception::Unknown(code),
+        }
+    }
+}
+
+impl Exception {
+    /// Checks if the exception was raised fetching the instruction, in which
+    /// case the instruction at `mepc` can't be read
+    fn is_fetch_fault(&self) -> bool {
+        matches!(
+            self,
+            Exception::InstructionMisaligned
+                | Exception::InstructionFault
+                | Exception::InstructionPageFault
+        )
+    }
+}
+
+/// What the exception handler does after printing the report
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExceptionPolicy {
+    /// Spin forever
+    Halt = 0,
+    /// Reset the SoC through the watchdog
+    Reset = 1,
+    /// Return to the instruction after the faulting one
+    Skip = 2,
+}
+
+impl From<u8> for ExceptionPolicy {
+    fn from(value: u8) -> Self {
+        match value {
+            1 => ExceptionPolicy::Reset,
+            2 => ExceptionPolicy::Skip,
+            _ => ExceptionPolicy::Halt,
+        }
+    }
+}
+
+static POLICY: AtomicU8 = AtomicU8::new(ExceptionPolicy::Halt as u8);
+/// Bit per hart that is currently handling an exception
+static IN_HANDLER: AtomicU8 = AtomicU8::new(0);
+
+/// Sets what the exception handler does after printing the report
+pub fn set_policy(policy: ExceptionPolicy) {
+    POLICY.store(policy as u8, Ordering::Relaxed);
+}
+
+pub fn policy() -> ExceptionPolicy {
+    ExceptionPolicy::from(POLICY.load(Ordering::Relaxed))
+}
+
+/// Reads the instruction at `address`.  Returns the instruction word and its
+/// length in bytes, 2 for compressed instructions.
+fn read_instruction(address: usize) -> (u32, usize) {
+    //Instructions are only 2 byte aligned, read them in halves
+    let low = unsafe { ptr::read_volatile(address as *const u16) } as u32;
+    if low & 0b11 != 0b11 {
+        return (low, 2);
+    }
+    let high = unsafe { ptr::read_volatile((address + 2) as *const u16) } as u32;
+    (high << 16 | low, 4)
+}
+
+/// Walks the frame pointer chain starting at the caller of this function.
+/// Every frame saves the return address at `fp - 8` and the caller's frame
+/// pointer at `fp - 16`.
+#[inline(never)]
+pub fn print_backtrace() {
+    let mut fp: usize;
+    unsafe { core::arch::asm!("mv {}, s0", out(reg) fp) };
+    log::print_force(format_args!("backtrace:\r\n"));
+    for frame in 0..MAX_FRAMES {
+        if !(STACK_LOW + 16..=STACK_HIGH).contains(&fp) || fp % 8 != 0 {
+            break;
+        }
+        let ra = unsafe { ptr::read_volatile((fp - 8) as *const usize) };
+        let previous_fp = unsafe { ptr::read_volatile((fp - 16) as *const usize) };
+        if ra == 0 {
+            break;
+        }
+        log::print_force(format_args!("  #{:<2} {:#018x}\r\n", frame, ra));
+        //Stacks grow down, the caller's frame is always above this one
+        if previous_fp <= fp {
+            break;
+        }
+        fp = previous_fp;
+    }
+}
+
+#[export_name = "ExceptionHandler"]
+fn custom_exception_handler(trap_frame: &riscv_rt::TrapFrame) {
+    let hart = HartId::current();
+    let hart_bit = 1 << hart as u8;
+    if IN_HANDLER.fetch_or(hart_bit, Ordering::Relaxed) & hart_bit != 0 {
+        //Faulted while reporting a fault, don't try again
+        ipi::halt();
+    }
+
+    let cause = mcause::read();
+    let exception = Exception::from(cause.code());
+    let epc = mepc::read();
+    //Record before printing, the UART may hang and the report never finish
+    crash::record_exception(trap_frame, format_args!("{:?}", exception));
+    log::print_force(format_args!("exception {:?} on {:?}\r\n", exception, hart));
+    log::print_force(format_args!("mcause:  {:#018x}\r\n", cause.bits()));
+    log::print_force(format_args!("mepc:    {:#018x}\r\n", epc));
+    log::print_force(format_args!("mtval:   {:#018x}\r\n", mtval::read()));
+    log::print_force(format_args!(
+        "mstatus: {:#018x}\r\n",
+        mstatus::read().bits()
+    ));
+    let instruction = match exception.is_fetch_fault() {
+        true => None,
+        false => Some(read_instruction(epc)),
+    };
+    if let Some((word, length)) = instruction {
+        log::print_force(format_args!(
+            "instruction: {:#0width$x}\r\n",
+            word,
+            width = 2 + 2 * length
+        ));
+    }
+    log::print_force(format_args!("{:?}\r\n", trap_frame));
+    print_backtrace();
+
+    match (policy(), instruction) {
+        (ExceptionPolicy::Skip, Some((_, length))) => {
+            log::print_force(format_args!("skipping faulting instruction\r\n"));
+            unsafe { mepc::write(epc + length) };
+            IN_HANDLER.fetch_and(!hart_bit, Ordering::Relaxed);
+        }
+        (ExceptionPolicy::Reset, _) => {
+            log::print_force(format_args!("resetting\r\n"));
+            watchdog::reset_soc();
+        }
+        _ => {
+            log::print_force(format_args!("halted\r\n"));
+            ipi::halt();
+        }
+    }
+}
@@ -1,8 +1,8 @@
 use embedded_hal::delay::DelayNs;
-use jh7110_hal::{clocks, ddr, delay, pac, pll, register::feature_disable};
-use riscv::register::{marchid, mhartid, mimpid, mstatus, mvendorid};
+use jh7110_hal::{clocks, ddr, pac, pll, register::feature_disable};
+use riscv::register::{marchid, mhartid, mimpid, mstatus, mtvec, mvendorid};
 
-use crate::println;
+use crate::{println, timer::Timer2};
 
 //use crate::println;
 
@@ -41,6 +41,36 @@ pub unsafe fn setup_features() {
     feature_disable::clear_all();
 }
 
+/// Mode bits of `mtvec`, 0 for direct and 1 for vectored
+const MTVEC_MODE_MASK: usize = 0b11;
+
+/// Checks in debug builds that riscv-rt set `mtvec` up for the mode the
+/// `v-trap` feature selects.  In vectored mode the base has to be the vector
+/// table, the hart jumps to `base + 4 * cause` from it.
+pub fn check_trap_vector() {
+    let mtvec = mtvec::read().bits();
+    #[cfg(feature = "v-trap")]
+    {
+        extern "C" {
+            fn _vector_table();
+        }
+        debug_assert_eq!(
+            mtvec & MTVEC_MODE_MASK,
+            1,
+            "mtvec {:#x} not vectored",
+            mtvec
+        );
+        debug_assert_eq!(
+            mtvec & !MTVEC_MODE_MASK,
+            _vector_table as usize,
+            "mtvec {:#x} not at the vector table",
+            mtvec
+        );
+    }
+    #[cfg(not(feature = "v-trap"))]
+    debug_assert_eq!(mtvec & MTVEC_MODE_MASK, 0, "mtvec {:#x} not direct", mtvec);
+}
+
 #[inline]
 pub unsafe fn setup_clocks() {
     //Steal the peripherals
@@ -98,8 +128,11 @@ pub unsafe fn setup_ddr() {
     clock_syscrg.reset_apb0();
     //Get a ddr preipheral for configuration
     let mut dram = ddr::Ddr::new(p.dmc_ctrl, p.dmc_phy, clock_syscrg.release(), p.sys_syscon);
-    //Get a delay
-    let mut udelay = delay::u74_mdelay();
+    //Get a delay, the channel is handed back once the DDR is up
+    let Some(mut udelay) = Timer2::take() else {
+        println!("Timer2 already taken, DDR not set up");
+        return;
+    };
     //TODO see why its being configured like this.  Not sure why its selecting
     //the bus clock between configuration and delays
     dram.select_bus_clock(clocks::ClkDdrBusMuxSel::ClkOscDiv2);
@@ -118,6 +151,7 @@ pub unsafe fn setup_ddr() {
     dram.select_bus_clock(clocks::ClkDdrBusMuxSel::ClkOscDiv2);
     // init the OMC (Orbit Memory Controller).
     dram.omc_init();
+    udelay.free();
 }
 
 /// Prints the boot mode of the device.
@@ -18,307 +18,548 @@
 //stable.  Id probably have to add extra logic around that to detect that when the signal is
 //detected as stable that it is still at the logic I expect, but that's the just of it.
 
+use core::{
+    fmt,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use heapless::spsc::{Consumer, Producer, Queue};
+
+pub use crate::debouncer::{DebounceConfig, LogicState, ReportMode};
 use crate::{
     array_vec::ArrayVec,
-    default_isr_this_has_to_be_wrong::{enable_interrupt, InterruptPriority},
-    log,
+    debouncer::Debouncer,
+    default_isr_this_has_to_be_wrong::{enable_interrupt, register_handler_ptr, InterruptPriority},
+};
+use crate::{
+    mono::{Duration, Instant},
+    println,
+    soft_timer::{self, TimerId},
+    timer::*,
 };
-use crate::{println, timer::*};
 use jh7110_hal::gpio::Pad;
 use jh7110_pac::{self as pac, Interrupt};
 
-#[derive(Copy, Clone, Debug, PartialEq)]
-pub enum LogicState {
-    Low,
-    High,
-    Unknown,
+/// Debounced edge of an input signal
+#[derive(Copy, Clone, Debug)]
+pub struct InputEvent {
+    pub pad: Pad,
+    /// Level the input changed to
+    pub state: LogicState,
+    /// When the edge was reported
+    pub timestamp: Instant,
 }
 
-impl From<bool> for LogicState {
-    fn from(is_high: bool) -> Self {
-        match is_high {
-            false => LogicState::Low,
-            true => LogicState::High,
-        }
+impl fmt::Display for InputEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {:?} {:?}", self.timestamp, self.pad, self.state)
     }
 }
 
+/// Handler called from the interrupt with a context pointer, type erased the
+/// same way as the interrupt handlers
 #[derive(Copy, Clone, Debug)]
-pub enum InputSignalState {
-    Unknown,
-    StableLow,
-    StabilizingHigh,
-    StableHigh,
-    StabilizingLow,
+struct SignalHandler {
+    trampoline: unsafe fn(*const (), Pad, LogicState, *mut ()),
+    handler: *const (),
+    context: *mut (),
+}
+
+unsafe fn call_signal_handler<Ctx>(
+    handler: *const (),
+    pad: Pad,
+    state: LogicState,
+    context: *mut (),
+) {
+    let handler: fn(Pad, LogicState, &mut Ctx) = core::mem::transmute(handler);
+    handler(pad, state, &mut *(context as *mut Ctx));
 }
 
 #[derive(Copy, Clone, Debug)]
 pub struct Signal {
     pin_number: Pad,
-    state: InputSignalState,
-    stabilization_counter: u8,
-    logic_state: LogicState,
-    edge_callback: fn(logic_state: LogicState),
+    /// Called with every debounced edge, after it's queued
+    handler: Option<SignalHandler>,
+    sense: Sense,
+    /// Set while a level interrupt is masked, waiting for its source to
+    /// deassert
+    masked: bool,
+    debouncer: Debouncer,
 }
 
 impl Signal {
-    pub fn new(pin_number: Pad, edge_callback: fn(logic_state: LogicState)) -> Self {
+    pub fn new(pin_number: Pad, sense: Sense, config: DebounceConfig) -> Self {
         Self {
             pin_number,
-            state: InputSignalState::Unknown,
-            stabilization_counter: 0,
-            logic_state: LogicState::Unknown,
-            edge_callback,
+            handler: None,
+            sense,
+            masked: false,
+            debouncer: Debouncer::new(config),
         }
     }
+}
 
-    pub fn process_edge(&mut self, state: LogicState) {
-        match self.state {
-            InputSignalState::StableLow => {
-                self.state = InputSignalState::StabilizingHigh;
-                self.stabilization_counter = 0;
-                (self.edge_callback)(LogicState::High);
-                if state == LogicState::Low {
-                    println!("***UNEXPECTED LOGIC LOW***");
-                }
-            }
-            InputSignalState::StableHigh => {
-                self.state = InputSignalState::StabilizingLow;
-                self.stabilization_counter = 0;
-                (self.edge_callback)(LogicState::Low);
-                if state == LogicState::High {
-                    println!("***UNEXPECTED LOGIC HIGH***");
-                }
-            }
-            InputSignalState::StabilizingLow => {
-                self.stabilization_counter = 0;
-                println!("dbl, ls: {:?}", state);
-            }
-            InputSignalState::StabilizingHigh => {
-                self.stabilization_counter = 0;
-                println!("dbh, ls: {:?}", state);
-            }
-            InputSignalState::Unknown => {
-                match state {
-                    LogicState::Low => {
-                        self.state = InputSignalState::StabilizingLow;
-                        self.stabilization_counter = 0;
-                    }
-                    LogicState::High => {
-                        self.state = InputSignalState::StabilizingLow;
-                        self.stabilization_counter = 0;
-                    }
-                    LogicState::Unknown => {
-                        println!("Unknown Logic State WTF!?");
-                    }
-                };
+const NUMBER_GPIO: usize = 64;
+const PADS_PER_REGISTER: usize = 32;
+
+/// Period of the [DebounceMode::Tick] timer
+const TICK_PERIOD: Duration = Duration::from_millis(10);
+/// How often a masked level interrupt is checked for its source deasserting
+/// in [DebounceMode::Timestamp]
+const LEVEL_POLL_PERIOD: Duration = Duration::from_millis(10);
+
+/// How signals are decided to be stable after an edge
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DebounceMode {
+    /// `TIMER0` ticks every [TICK_PERIOD] and a signal is stable after its
+    /// settle time in ticks without an edge
+    Tick,
+    /// Every edge is timestamped and a soft timer is armed for the earliest
+    /// settle deadline only while a signal is unstable.  No timer
+    /// channel is used and no interrupt fires while all signals are stable.
+    Timestamp,
+}
+
+/// State shared by the edge and debounce interrupt handlers
+struct InputSignals {
+    signals: ArrayVec<Signal, NUMBER_GPIO>,
+    mode: DebounceMode,
+    /// Debounce tick timer, owned here so nothing else can take the channel
+    timer: Option<Timer0>,
+    /// Pending settle deadline of [DebounceMode::Timestamp] and when it fires
+    settle_timer: Option<(TimerId, Instant)>,
+    /// Debounced edges, drained by [next_event]
+    events: Option<Producer<'static, InputEvent, EVENT_QUEUE_SIZE>>,
+}
+
+impl InputSignals {
+    /// Arms the soft timer for the earliest settle deadline.  A timer already
+    /// armed is kept if it fires no later, otherwise it's cancelled and armed
+    /// again, signals settle at different times.  A timer that fires early
+    /// re-arms for the rest.  While a level interrupt is masked, or a signal
+    /// is stable at a level its sense raises no interrupt for leaving, it also
+    /// polls every [LEVEL_POLL_PERIOD] so the debouncer sees the level change.
+    fn arm_settle_timer(&mut self) {
+        let poll = self
+            .signals
+            .iter()
+            .any(|s| {
+                s.masked
+                    || s.debouncer
+                        .stable_level()
+                        .is_some_and(|level| !s.sense.interrupts_leaving(level))
+            })
+            .then(|| Instant::now() + LEVEL_POLL_PERIOD);
+        let Some(deadline) = self
+            .signals
+            .iter()
+            .filter_map(|s| s.debouncer.settle_deadline())
+            .chain(poll)
+            .min()
+        else {
+            return;
+        };
+        if let Some((id, armed)) = self.settle_timer {
+            //Already expired means its callback is about to run and re-arm
+            if armed <= deadline || !soft_timer::cancel(id) {
+                return;
             }
+            self.settle_timer = None;
+        }
+        match soft_timer::add_at(deadline, settle_deadline_handler) {
+            Ok(id) => self.settle_timer = Some((id, deadline)),
+            Err(e) => println!("Settle timer not armed: {:?}", e),
         }
     }
 
-    pub fn process_debounce_tick(&mut self, logic_state: LogicState) {
-        match self.state {
-            InputSignalState::StabilizingLow => {
-                self.stabilization_counter += 1;
-                if self.stabilization_counter == 5 {
-                    if logic_state == LogicState::Low {
-                        self.state = InputSignalState::StableLow;
-                        self.logic_state = logic_state;
-                        self.stabilization_counter = 0;
-                    } else {
-                        println!("Stabelizing Low, But Signal High.");
-                        self.state = InputSignalState::StabilizingHigh;
-                        self.stabilization_counter = 0;
-                    }
-                }
-            }
-            InputSignalState::StabilizingHigh => {
-                self.stabilization_counter += 1;
-                if self.stabilization_counter == 5 {
-                    if logic_state == LogicState::High {
-                        self.state = InputSignalState::StableHigh;
-                        self.logic_state = logic_state;
-                        self.stabilization_counter = 0;
-                    } else {
-                        println!("Stabelizing High, But Signal Low.");
-                        self.state = InputSignalState::StabilizingLow;
-                        self.stabilization_counter = 0;
-                    }
-                }
-            }
-            InputSignalState::Unknown => {
-                //Set to stabelizing the direction of the pin
-                match logic_state {
-                    LogicState::Low => {
-                        self.state = InputSignalState::StabilizingLow;
-                        self.stabilization_counter = 0;
-                    }
-                    LogicState::High => {
-                        self.state = InputSignalState::StabilizingHigh;
-                        self.stabilization_counter = 0;
-                    }
-                    LogicState::Unknown => {
-                        println!("wtf");
-                        self.state = InputSignalState::StabilizingLow;
-                        self.stabilization_counter = 0;
-                    }
-                }
+    /// Re-enables the level interrupts whose source deasserted
+    fn unmask_deasserted(&mut self, sync: u64) {
+        for s in self.signals.iter_mut() {
+            let is_high = (sync & (1 << (s.pin_number as u64))) != 0;
+            if s.masked && !s.sense.is_asserted(is_high) {
+                s.masked = false;
+                modify_irq_bit(s.pin_number as u32, IrqRegister::Enable, true);
             }
-            _ => { /*Not wure what to do yet*/ }
         }
     }
 }
 
-const NUMBER_GPIO: usize = 63;
-const PADS_PER_REGISTER: usize = 32;
+//The signal list is handed to the interrupt handlers.  The soft timer callback
+//takes no context so it reaches for it here, it runs from TIMER1 with the same
+//priority on the same hart as the others so they never preempt each other.
+static mut INPUT_SIGNALS: InputSignals = InputSignals {
+    signals: ArrayVec::new(),
+    mode: DebounceMode::Tick,
+    timer: None,
+    settle_timer: None,
+    events: None,
+};
+
+/// Size of the event queue, it holds one event less
+const EVENT_QUEUE_SIZE: usize = 32;
+static mut EVENTS: Queue<InputEvent, EVENT_QUEUE_SIZE> = Queue::new();
+static EVENT_CONSUMER: spin::Mutex<Option<Consumer<'static, InputEvent, EVENT_QUEUE_SIZE>>> =
+    spin::Mutex::new(None);
+static DROPPED_EVENTS: AtomicU32 = AtomicU32::new(0);
+
+/// Reads the level of every pad from the Block0 and Block1 sync registers
+fn read_sync() -> u64 {
+    let pinctrl = unsafe { &*pac::SysPinctrl::ptr() };
+    let sync0 = pinctrl.ioirq().ioirq15().read().bits();
+    let sync1 = pinctrl.ioirq().ioirq16().read().bits();
+    (sync1 as u64) << 32 | (sync0 as u64)
+}
+
+/// Pull resistor of an input pad
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Pull {
+    None,
+    Up,
+    Down,
+}
+
+/// What on an input raises the interrupt
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Sense {
+    Rising,
+    Falling,
+    /// Rising and falling edges
+    Both,
+    /// Pending for as long as the input is high
+    High,
+    /// Pending for as long as the input is low
+    Low,
+}
+
+impl Sense {
+    pub fn is_level(self) -> bool {
+        matches!(self, Sense::High | Sense::Low)
+    }
+
+    /// Level the input went to when the interrupt fired, None for both edges
+    fn moved_to(self) -> Option<LogicState> {
+        match self {
+            Sense::Rising | Sense::High => Some(LogicState::High),
+            Sense::Falling | Sense::Low => Some(LogicState::Low),
+            Sense::Both => None,
+        }
+    }
+
+    /// Checks if the input leaving `level` raises an interrupt
+    fn interrupts_leaving(self, level: LogicState) -> bool {
+        match self.moved_to() {
+            Some(moved_to) => moved_to != level,
+            None => true,
+        }
+    }
+
+    /// Checks if a level interrupt is still raised by the input, always false
+    /// for edges
+    fn is_asserted(self, is_high: bool) -> bool {
+        match self {
+            Sense::High => is_high,
+            Sense::Low => !is_high,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum InputError {
+    /// Every signal slot is in use
+    Full,
+    /// The pad already is an input signal
+    AlreadyAdded(Pad),
+    /// The pad isn't an input signal
+    NotAdded(Pad),
+}
+
+/// Output enable registers, one byte per pad with the enable in the low 6 bits
+const GPO_DOEN_OFFSET: usize = 0x000;
+const GPO_DOEN_MASK: u32 = 0x3F;
+/// 0 drives the pad from its output, 1 leaves it an input
+const GPO_DOEN_INPUT: u32 = 1;
+/// Pad configuration registers, one per pad
+const PADCFG_OFFSET: usize = 0x120;
+const PADCFG_IE: u32 = 1 << 0;
+const PADCFG_PU: u32 = 1 << 3;
+const PADCFG_PD: u32 = 1 << 4;
+const PADCFG_SMT: u32 = 1 << 6;
+
+fn gpo_doen_address(pad: u32) -> usize {
+    pac::SysPinctrl::ptr() as usize + GPO_DOEN_OFFSET + 4 * (pad as usize / 4)
+}
+
+fn padcfg_address(pad: u32) -> usize {
+    pac::SysPinctrl::ptr() as usize + PADCFG_OFFSET + 4 * pad as usize
+}
+
+/// IOIRQ registers with a bit per pad, split in Block0 for pads 0-31 and
+/// Block1 for pads 32-63
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum IrqRegister {
+    /// Interrupt Sense (IS), 1 for edge and 0 for level trigger
+    Sense,
+    /// Interrupt Clear (IC), write 0 and then 1 to clear an edge interrupt
+    Clear,
+    /// Interrupt Both Edges (IBE)
+    BothEdges,
+    /// Interrupt Event (IEV), 1 for rising edge or high level, 0 for falling
+    /// edge or low level
+    Event,
+    /// Interrupt Enable (IE)
+    Enable,
+}
+
+/// Sets or clears the pad's bit in the IOIRQ register of its block
+fn modify_irq_bit(pad: u32, register: IrqRegister, set: bool) {
+    let mask = 1 << (pad % PADS_PER_REGISTER as u32);
+    let update = |bits: u32| match set {
+        true => bits | mask,
+        false => bits & !mask,
+    };
+    let ioirq = unsafe { &*pac::SysPinctrl::ptr() }.ioirq();
+    let block0 = pad < PADS_PER_REGISTER as u32;
+    match (register, block0) {
+        //SYS IOMUX CFGSAIF SYSCFG IOIRQ 56/57
+        (IrqRegister::Sense, true) => ioirq
+            .ioirq1()
+            .modify(|r, w| w.is0().variant(update(r.is0().bits()))),
+        (IrqRegister::Sense, false) => ioirq
+            .ioirq2()
+            .modify(|r, w| w.is1().variant(update(r.is1().bits()))),
+        //SYS IOMUX CFGSAIF SYSCFG IOIRQ 58/59
+        (IrqRegister::Clear, true) => ioirq
+            .ioirq3()
+            .modify(|r, w| w.ic0().variant(update(r.ic0().bits()))),
+        (IrqRegister::Clear, false) => ioirq
+            .ioirq4()
+            .modify(|r, w| w.ic1().variant(update(r.ic1().bits()))),
+        //SYS IOMUX CFGSAIF SYSCFG IOIRQ 60/61
+        (IrqRegister::BothEdges, true) => ioirq
+            .ioirq5()
+            .modify(|r, w| w.ibe0().variant(update(r.ibe0().bits()))),
+        (IrqRegister::BothEdges, false) => ioirq
+            .ioirq6()
+            .modify(|r, w| w.ibe1().variant(update(r.ibe1().bits()))),
+        //SYS IOMUX CFGSAIF SYSCFG IOIRQ 62/63
+        (IrqRegister::Event, true) => ioirq
+            .ioirq7()
+            .modify(|r, w| w.iev0().variant(update(r.iev0().bits()))),
+        (IrqRegister::Event, false) => ioirq
+            .ioirq8()
+            .modify(|r, w| w.iev1().variant(update(r.iev1().bits()))),
+        //SYS IOMUX CFGSAIF SYSCFG IOIRQ 64/65
+        (IrqRegister::Enable, true) => ioirq
+            .ioirq9()
+            .modify(|r, w| w.ie0().variant(update(r.ie0().bits()))),
+        (IrqRegister::Enable, false) => ioirq
+            .ioirq10()
+            .modify(|r, w| w.ie1().variant(update(r.ie1().bits()))),
+    }
+}
+
+/// Makes the pad an input with the pull and schmitt trigger
+fn configure_pad(pad: u32, pull: Pull, schmitt: bool) {
+    let shift = 8 * (pad % 4);
+    let doen = gpo_doen_address(pad) as *mut u32;
+    unsafe {
+        let value = doen.read_volatile() & !(GPO_DOEN_MASK << shift);
+        doen.write_volatile(value | GPO_DOEN_INPUT << shift);
+    }
+    //Drive strength and slew rate stay at their lowest
+    let mut padcfg = PADCFG_IE;
+    padcfg |= match pull {
+        Pull::None => 0,
+        Pull::Up => PADCFG_PU,
+        Pull::Down => PADCFG_PD,
+    };
+    if schmitt {
+        padcfg |= PADCFG_SMT;
+    }
+    unsafe { (padcfg_address(pad) as *mut u32).write_volatile(padcfg) };
+}
+
+/// Sets up the IOIRQ registers of the pad for the sense and enables its
+/// interrupt
+fn configure_irq(pad: u32, sense: Sense) {
+    modify_irq_bit(pad, IrqRegister::Enable, false);
+    modify_irq_bit(pad, IrqRegister::Sense, !sense.is_level());
+    modify_irq_bit(pad, IrqRegister::BothEdges, sense == Sense::Both);
+    modify_irq_bit(
+        pad,
+        IrqRegister::Event,
+        matches!(sense, Sense::Rising | Sense::High),
+    );
+    //Clear anything latched while it was being set up
+    modify_irq_bit(pad, IrqRegister::Clear, false);
+    modify_irq_bit(pad, IrqRegister::Clear, true);
+    modify_irq_bit(pad, IrqRegister::Enable, true);
+}
+
+/// Makes any of the 64 SYS GPIO pads a debounced input.  Its edges are queued
+/// as [InputEvent]s for [next_event].  [configure] has to be called first.
+pub fn add_input(
+    pad: Pad,
+    pull: Pull,
+    schmitt: bool,
+    sense: Sense,
+    config: DebounceConfig,
+) -> Result<(), InputError> {
+    let pad_number = pad as u32;
+    riscv::interrupt::free(|| {
+        let input_signals = unsafe { &mut *core::ptr::addr_of_mut!(INPUT_SIGNALS) };
+        if input_signals
+            .signals
+            .iter()
+            .any(|s| s.pin_number as u32 == pad_number)
+        {
+            return Err(InputError::AlreadyAdded(pad));
+        }
+        if input_signals.signals.is_full() {
+            return Err(InputError::Full);
+        }
+        //Only touch the pad once it's sure to become a signal, a rejected
+        //call must not reconfigure a pad that is already live
+        configure_pad(pad_number, pull, schmitt);
+        let mut signal = Signal::new(pad, sense, config);
+        if input_signals.mode == DebounceMode::Timestamp {
+            //Start stabilizing on the current level
+            let is_high = read_sync() & (1 << pad_number as u64) != 0;
+            signal
+                .debouncer
+                .process_settle_deadline(LogicState::from(is_high), Instant::now());
+        }
+        if input_signals.signals.try_push(signal).is_err() {
+            return Err(InputError::Full);
+        }
+        configure_irq(pad_number, sense);
+        if input_signals.mode == DebounceMode::Timestamp {
+            input_signals.arm_settle_timer();
+        }
+        Ok(())
+    })
+}
+
+/// Sets the handler called from the interrupt with every debounced edge of
+/// the pad, after the edge is queued.
+///
+/// # Safety
+///
+/// `context` must stay valid until the handler is replaced with
+/// [clear_handler], and nothing else may access it while the handler runs.
+pub unsafe fn set_handler<Ctx>(
+    pad: Pad,
+    handler: fn(Pad, LogicState, &mut Ctx),
+    context: *mut Ctx,
+) -> Result<(), InputError> {
+    let handler = SignalHandler {
+        trampoline: call_signal_handler::<Ctx>,
+        handler: handler as *const (),
+        context: context as *mut (),
+    };
+    with_signal(pad, |s| s.handler = Some(handler))
+}
 
-static mut SIGNALS: ArrayVec<Signal, NUMBER_GPIO> = ArrayVec::new();
+/// Removes the handler of the pad, its edges are still queued
+pub fn clear_handler(pad: Pad) -> Result<(), InputError> {
+    with_signal(pad, |s| s.handler = None)
+}
+
+/// Checks if the pad bounced more than [DebounceConfig::max_bounces] times
+/// while stabilizing since the last call, a sign of a worn or failing switch
+pub fn take_noisy(pad: Pad) -> Result<bool, InputError> {
+    with_signal(pad, |s| s.debouncer.take_noisy())
+}
+
+/// Takes the next pad flagged noisy like [take_noisy], for the main loop to
+/// drain and report
+pub fn next_noisy() -> Option<Pad> {
+    riscv::interrupt::free(|| {
+        let input_signals = unsafe { &mut *core::ptr::addr_of_mut!(INPUT_SIGNALS) };
+        input_signals
+            .signals
+            .iter_mut()
+            .find_map(|s| s.debouncer.take_noisy().then_some(s.pin_number))
+    })
+}
+
+/// Runs `f` on the signal of the pad with the interrupts off
+fn with_signal<R>(pad: Pad, f: impl FnOnce(&mut Signal) -> R) -> Result<R, InputError> {
+    riscv::interrupt::free(|| {
+        let input_signals = unsafe { &mut *core::ptr::addr_of_mut!(INPUT_SIGNALS) };
+        let signal = input_signals
+            .signals
+            .iter_mut()
+            .find(|s| s.pin_number as u32 == pad as u32)
+            .ok_or(InputError::NotAdded(pad))?;
+        Ok(f(signal))
+    })
+}
+
+/// Takes the oldest queued edge.  Called from the main loop, only one hart
+/// may drain the queue.
+pub fn next_event() -> Option<InputEvent> {
+    EVENT_CONSUMER.lock().as_mut()?.dequeue()
+}
+
+/// Number of edges lost because the queue was full
+pub fn dropped_events() -> u32 {
+    DROPPED_EVENTS.load(Ordering::Relaxed)
+}
+
+pub fn configure(mode: DebounceMode) {
+    let input_signals = unsafe { &mut *core::ptr::addr_of_mut!(INPUT_SIGNALS) };
+    input_signals.mode = mode;
 
-pub fn configure() {
     //Setup input_signal structure list
     //Set length here.  There seems to be an error with initialization.  Refer to below for fix
     // https://docs.rust-embedded.org/embedonomicon/main.html#life-before-main
-    unsafe {
-        SIGNALS.init();
-        match SIGNALS.try_push(Signal::new(Pad::Gpio37, edge_callback)) {
-            Err(s) => {
-                println!("Failed insert of signal for pin {:?}", s.pin_number);
-            }
-            Ok(_) => {}
-        }
+    input_signals.signals.init();
+
+    if input_signals.events.is_none() {
+        //Only ever split once, so the producer and consumer are the only
+        //references to the queue
+        let (producer, consumer) = unsafe { (*core::ptr::addr_of_mut!(EVENTS)).split() };
+        input_signals.events = Some(producer);
+        *EVENT_CONSUMER.lock() = Some(consumer);
     }
 
-    //Get GPIO
-    let pinctrl = unsafe { &*pac::SysPinctrl::ptr() };
-    //Setup the output enable function for pin 37 (0)
-    pinctrl
-        .gpo_doen()
-        .gpo_doen9()
-        .modify(|_, w| w.doen37().variant(1)); //Set pin as an input (0 output, 1 input)
-
-    //Setup the pad config for pin 37
-    //Set to an input with pull-up enabled
-    pinctrl.padcfg().gpio37().modify(|_, w| {
-        w.ie()
-            .set_bit() //enable input
-            .ds()
-            .variant(0b00) //output strength lowest 2mA
-            .pu()
-            .set_bit() //enable the pull-up
-            .pd()
-            .clear_bit() //disable the pull-down
-            .slew()
-            .clear_bit() //set slew rate to slow (dont care)
-            .smt()
-            .set_bit()
-            //.clear_bit() //disable the schmitt trigger for now (May want to enable later, will help
-            //with switch bouncing)
-            .pos()
-            .clear_bit() //disable active pull down capability
-    });
-
-    //Setup GPIO interrupt
-    //IS (Interrupt Sense) = 1
-    //IBE (Interrupt Both Edges) = 1
-    //IEV (Interrupt Event) = Don't care
-    let pad = 37u32;
-    let mask = 1 << (pad - PADS_PER_REGISTER as u32);
     //Enable GPIO IRQ function.  Note this also is needed just to enable reading of pins
     //SYS IOMUX CFGSAIF SYSCFG IOIRQ 55 (Enable IRQ Function)
+    let pinctrl = unsafe { &*pac::SysPinctrl::ptr() };
     pinctrl.ioirq().ioirq0().write(|w| w.gpen0().set_bit());
-    //Block 0 - pins 0-31
-    //Block 1 - pins 32-63
-    //SYS IOMUX CFGSAIF SYSCFG IOIRQ 56 Block0 Interrupt Sense (IS) EDGE or LEVEL Trigger
-    //pinctrl
-    //  .ioirq()
-    //  .ioirq1()
-    //SYS IOMUX CFGSAIF SYSCFG IOIRQ 57 Block1 Interrupt Sense (IS) EDGE or LEVEL Trigger
-    //Set edge triggering bit corsponding to pin (37 for now)
-    pinctrl
-        .ioirq()
-        .ioirq2()
-        .modify(|r, w| w.is1().variant(r.is1().bits() | mask));
-    //Not sure what these are for.  Sets a bit to "Do not clear the register" or
-    //"Clear the register". What register? This is where the interrupt it cleared
-    //SYS IOMUX CFGSAIF SYSCFG IOIRQ 58 Block0 Interrupt Clear (IC)
-    //pinctrl
-    //  .ioirq()
-    //  .ioirq3()
-    //SYS IOMUX CFGSAIF SYSCFG IOIRQ 59 Block1 Interrupt Clear (IC)
-    //Clear it for now for 37.  Will have to clear it after handeling it
-    pinctrl
-        .ioirq()
-        .ioirq4()
-        .modify(|r, w| w.ic1().variant(r.ic1().bits() | mask));
-    //SYS IOMUX CFGSAIF SYSCFG IOIRQ 60 Block0 Interrupt Both Edges (IBE)
-    //pinctrl
-    //  .ioirq()
-    //  .ioirq5()
-    //SYS IOMUX CFGSAIF SYSCFG IOIRQ 61 Block1 Interrupt Both Edges (IBE)
-    pinctrl
-        .ioirq()
-        .ioirq6()
-        .modify(|r, w| w.ibe1().variant(r.ibe1().bits() | mask));
-    //SYS IOMUX CFGSAIF SYSCFG IOIRQ 62 Block0 Interrupt Event (IEV)
-    //pinctrl.ioirq().ioirq7()
-    //SYS IOMUX CFGSAIF SYSCFG IOIRQ 63 Block1 Interrupt Event (IEV)
-    //Pisitive/Low trigger or Negative/High trigger
-    //I dont think we care about this since we are triggering on both edges, but the
-    //linux driver is clearing the bit, so lets do that.
-    pinctrl
-        .ioirq()
-        .ioirq8()
-        .modify(|r, w| w.iev1().variant(r.iev1().bits() | !mask));
-    //SYS IOMUX CFGSAIF SYSCFG IOIRQ 64 Block0 Interrupt Enable (IE)
-    //pinctrl.ioirq().ioirq9()
-    //SYS IOMUX CFGSAIF SYSCFG IOIRQ 65 Block1 Interrupt Enable (IE)
-    pinctrl
-        .ioirq()
-        .ioirq10()
-        .modify(|r, w| w.ie1().variant(r.ie1().bits() | mask));
-    //Dont care about these two right now
-    //SYS IOMUX CFGSAIF SYSCFG IOIRQ 66 Block0 Raw Interrupt Status (RIS)
-    //pinctrl.ioirq().ioirq11()
-    //SYS IOMUX CFGSAIF SYSCFG IOIRQ 67 Block1 Raw Interrupt Status (RIS)
-    //pinctrl.ioirq().ioirq12()
-    //
-    //Dont care about these for now.  They are used to determin what interrupts caused ISR
-    //SYS IOMUX CFGSAIF SYSCFG IOIRQ 68 Block0 Masked Interrupt Status (MIS)
-    //pinctrl.ioirq().ioirq13
-    //SYS IOMUX CFGSAIF SYSCFG IOIRQ 69 BLOCK1 Masked Interrupt Status (MIS)
-    //pinctrl.ioirq().ioirq14()
-    //
-    //Dont care about these for now.  This is where the value of the signal is read
-    //SYS IOMUX CFGSAIF SYSCFG IOIRQ 70 Block0 Sync register
-    //pinctrl.ioirq().ioirq15()
-    //SYS IOMUX CFGSAIF SYSCFG IOIRQ 71 Block1 Sunc register
-    //pinctrl.ioirq().ioirq16()
-
-    println!("Setting up crg");
-    //Setup timer and timer interrupt
-    let sys_crg = unsafe { &*pac::Syscrg::ptr() };
-    //Enable the timer Advanced Preriphial BUS clock
-    sys_crg.clk_tim().apb().modify(|_, w| w.clk_icg().set_bit());
-    //Enable the timer clock
-    sys_crg
-        .clk_tim()
-        .tim01_0()
-        .modify(|_, w| w.clk_icg().set_bit());
-    //Clear the timer abp reset bit
-    sys_crg
-        .rst()
-        .software_address_selector()
-        .rst3()
-        .modify(|_, w| w.u0_si5_timer_apb().clear_bit());
-    //Clear the timer reset bit
-    sys_crg
-        .rst()
-        .software_address_selector()
-        .rst3()
-        .modify(|_, w| w.u0_si5_timer_0().clear_bit());
-
-    //Setup the timer
-    let t0 = Timer0::new();
+
+    if mode == DebounceMode::Tick {
+        configure_tick_timer(input_signals);
+    }
+
+    unsafe {
+        if register_handler_ptr(Interrupt::SYS_IOMUX, signal_change_handler, input_signals).is_err()
+        {
+            println!("SYS_IOMUX handler already registered");
+        }
+    }
+    enable_interrupt(Interrupt::SYS_IOMUX, InterruptPriority::Priority5);
+
+    //Switch on pin 37 to ground
+    if let Err(e) = add_input(
+        Pad::Gpio37,
+        Pull::Up,
+        true,
+        Sense::Both,
+        DebounceConfig::MECHANICAL,
+    ) {
+        println!("Failed to add input: {:?}", e);
+    }
+}
+
+fn configure_tick_timer(input_signals: &mut InputSignals) {
+    //Setup the timer, taking it brings up its clock and reset
+    let Some(t0) = Timer0::take() else {
+        println!("Timer0 already taken");
+        return;
+    };
     //Mask the interrupt
     t0.set_int_mask(TimerIntMask::Mask);
     match t0.get_int_clear_busy() {
@@ -327,30 +568,51 @@ pub fn configure() {
         }
         TimerIntClearBusy::No => {
             t0.set_int_status_clear(TimerIntClearStatus::Clear);
-            //So the apb is at 24MHz and I want this to fire every 10ms.
-            //24000000*.01=240000
-            t0.set_load(240000);
+            if let Err(e) = t0.set_period(TICK_PERIOD) {
+                println!("Timer0 period not set: {:?}", e);
+            }
             //t0.reload_counter();
             t0.set_int_mask(TimerIntMask::Unmask);
             t0.set_enable(TimerEnable::Enable);
         }
     }
+    input_signals.timer = Some(t0);
 
-    //
-    enable_interrupt(Interrupt::SYS_IOMUX, InterruptPriority::Priority7);
-    enable_interrupt(Interrupt::TIMER0, InterruptPriority::Priority7);
+    //Both handlers share the signal list.  They are enabled with the same
+    //priority on the same hart so they never preempt each other.
+    unsafe {
+        if register_handler_ptr(
+            Interrupt::TIMER0,
+            input_signal_timer_interrupt_handler,
+            input_signals,
+        )
+        .is_err()
+        {
+            println!("TIMER0 handler already registered");
+        }
+    }
+    enable_interrupt(Interrupt::TIMER0, InterruptPriority::Priority5);
 }
 
-pac::interrupt!(SYS_IOMUX, signal_change_handler);
-#[no_mangle]
-fn signal_change_handler() {
+fn signal_change_handler(input_signals: &mut InputSignals) {
     //Read Block0 MIS
     //Read Block1 MIS
     let pinctrl = unsafe { &*pac::SysPinctrl::ptr() };
     let mis0 = pinctrl.ioirq().ioirq13().read().bits();
     let mis1 = pinctrl.ioirq().ioirq14().read().bits();
-    let sync0 = pinctrl.ioirq().ioirq15().read().bits();
-    let sync1 = pinctrl.ioirq().ioirq16().read().bits();
+    let sync = read_sync();
+    let now = Instant::now();
+
+    let mis: u64 = (mis1 as u64) << 32 | (mis0 as u64);
+    //Only edge interrupts are cleared, level ones are masked below
+    let mut level: u64 = 0;
+    for s in input_signals.signals.iter() {
+        if s.sense.is_level() {
+            level |= 1 << (s.pin_number as u64);
+        }
+    }
+    let edge0 = mis0 & !(level as u32);
+    let edge1 = mis1 & !((level >> 32) as u32);
 
     //Note from TRM:  You can also write 0 and 1 sequentially to clear edge IRQ.
     //Writing just 1 didnt clear and writing 0 just disabled
@@ -358,112 +620,121 @@ fn signal_change_handler() {
     pinctrl
         .ioirq()
         .ioirq3()
-        .modify(|r, w| w.ic0().variant(r.ic0().bits() & !mis0));
+        .modify(|r, w| w.ic0().variant(r.ic0().bits() & !edge0));
     pinctrl
         .ioirq()
         .ioirq3()
-        .modify(|r, w| w.ic0().variant(r.ic0().bits() | mis0));
+        .modify(|r, w| w.ic0().variant(r.ic0().bits() | edge0));
     //Write to Block1 IC
     pinctrl
         .ioirq()
         .ioirq4()
-        .modify(|r, w| w.ic1().variant(r.ic1().bits() & !mis1));
+        .modify(|r, w| w.ic1().variant(r.ic1().bits() & !edge1));
     pinctrl
         .ioirq()
         .ioirq4()
-        .modify(|r, w| w.ic1().variant(r.ic1().bits() | mis1));
-
-    let mis: u64 = (mis1 as u64) << 32 | (mis0 as u64);
-    let sync: u64 = (sync1 as u64) << 32 | (sync0 as u64);
+        .modify(|r, w| w.ic1().variant(r.ic1().bits() | edge1));
 
     //println!("MIS{:#18x}", mis);
     //Check if any of these match out signals, read sync, update signal, do call back
-    unsafe {
-        for s in SIGNALS.iter_mut() {
-            let pin_mask = 1 << (s.pin_number as u64);
-            if mis & pin_mask != 0 {
-                let is_high = (sync & pin_mask) != 0;
-                //println!("E{:#18x}:{:#18x}", sync, pin_mask);
-                s.process_edge(LogicState::from(is_high));
+    for s in input_signals.signals.iter_mut() {
+        let pin_mask = 1 << (s.pin_number as u64);
+        if mis & pin_mask != 0 {
+            let is_high = (sync & pin_mask) != 0;
+            //println!("E{:#18x}:{:#18x}", sync, pin_mask);
+            //A level interrupt stays pending until its source deasserts.
+            //Mask it until then, the debounce timer unmasks it.
+            if s.sense.is_level() {
+                modify_irq_bit(s.pin_number as u32, IrqRegister::Enable, false);
+                s.masked = true;
+            }
+            if let Some(state) =
+                s.debouncer
+                    .process_edge(LogicState::from(is_high), s.sense.moved_to(), now)
+            {
+                let event = InputEvent {
+                    pad: s.pin_number,
+                    state,
+                    timestamp: now,
+                };
+                report(&mut input_signals.events, s, event);
             }
         }
     }
+    if input_signals.mode == DebounceMode::Timestamp {
+        input_signals.arm_settle_timer();
+    }
 }
 
-pac::interrupt!(TIMER0, input_signal_timer_interrupt_handler);
-#[no_mangle]
-fn input_signal_timer_interrupt_handler() {
-    let pinctrl = unsafe { &*pac::SysPinctrl::ptr() };
-    let sync0 = pinctrl.ioirq().ioirq15().read().bits();
-    let sync1 = pinctrl.ioirq().ioirq16().read().bits();
-    //Do the thing
-    let sync: u64 = (sync1 as u64) << 32 | (sync0 as u64);
+fn input_signal_timer_interrupt_handler(input_signals: &mut InputSignals) {
+    let sync = read_sync();
+    let now = Instant::now();
 
     //Check if any of these match out signals, read sync, update signal, do call back
-    unsafe {
-        for s in SIGNALS.iter_mut() {
-            let pin_mask = 1 << (s.pin_number as u64);
-            let is_high = (sync & pin_mask) != 0;
-            //println!("T{:#18x}:{:#18x}", sync, pin_mask);
-            s.process_debounce_tick(LogicState::from(is_high));
+    for s in input_signals.signals.iter_mut() {
+        let pin_mask = 1 << (s.pin_number as u64);
+        let is_high = (sync & pin_mask) != 0;
+        //println!("T{:#18x}:{:#18x}", sync, pin_mask);
+        if let Some(state) = s
+            .debouncer
+            .process_debounce_tick(LogicState::from(is_high), TICK_PERIOD)
+        {
+            let event = InputEvent {
+                pad: s.pin_number,
+                state,
+                timestamp: now,
+            };
+            report(&mut input_signals.events, s, event);
         }
     }
+    input_signals.unmask_deasserted(sync);
 
     //Clear the interrupt status
-    let t0 = Timer0::new();
-    t0.set_int_status_clear(TimerIntClearStatus::Clear);
-}
-
-fn edge_callback(logic_state: LogicState) {
-    println!("Switch Event {:?}", logic_state);
-}
-
-fn run_test() {
-    println!("Before insert loop");
-    for i in 1..11 {
-        println!("Signal Created");
-        let s = Signal {
-            pin_number: Pad::from(i),
-            state: InputSignalState::Unknown,
-            stabilization_counter: 0,
-            logic_state: LogicState::Unknown,
-            edge_callback,
-        };
-
-        println!("Before Push");
-
-        unsafe {
-            if let Err(_) = SIGNALS.try_push(s) {
-                println!("Error Push");
-            }
-        };
+    if let Some(t0) = &input_signals.timer {
+        t0.set_int_status_clear(TimerIntClearStatus::Clear);
     }
-    println!("After insert loop");
+}
 
-    unsafe {
-        for s in SIGNALS.iter() {
-            println!("Hi: {:?}", s.pin_number);
+/// Soft timer callback of [DebounceMode::Timestamp], settles the signals whose
+/// deadline passed and re-arms for the ones still unstable
+fn settle_deadline_handler() {
+    let input_signals = unsafe { &mut *core::ptr::addr_of_mut!(INPUT_SIGNALS) };
+    input_signals.settle_timer = None;
+    let sync = read_sync();
+    let now = Instant::now();
+    for s in input_signals.signals.iter_mut() {
+        let is_high = (sync & (1 << (s.pin_number as u64))) != 0;
+        if let Some(state) = s
+            .debouncer
+            .process_settle_deadline(LogicState::from(is_high), now)
+        {
+            let event = InputEvent {
+                pad: s.pin_number,
+                state,
+                timestamp: now,
+            };
+            report(&mut input_signals.events, s, event);
         }
     }
+    input_signals.unmask_deasserted(sync);
+    input_signals.arm_settle_timer();
+}
 
-    println!("After interator");
-
-    println!("Before Mut Interator");
-    unsafe {
-        for s in SIGNALS.iter_mut() {
-            s.pin_number = Pad::from(s.pin_number as u32 + 10);
-        }
+/// Queues the event and calls the signal's handler.  Runs in the interrupt, so
+/// nothing here may block.
+fn report(
+    events: &mut Option<Producer<'static, InputEvent, EVENT_QUEUE_SIZE>>,
+    signal: &Signal,
+    event: InputEvent,
+) {
+    let queued = match events {
+        Some(producer) => producer.enqueue(event).is_ok(),
+        None => false,
+    };
+    if !queued {
+        DROPPED_EVENTS.fetch_add(1, Ordering::Relaxed);
     }
-
-    println!("After Mut Iterator");
-
-    println!("After mut interator loop");
-
-    unsafe {
-        for s in SIGNALS.iter() {
-            println!("Hi: {:?}", s.pin_number);
-        }
+    if let Some(h) = signal.handler {
+        unsafe { (h.trampoline)(h.handler, event.pad, event.state, h.context) };
     }
-
-    println!("After mut interator");
 }
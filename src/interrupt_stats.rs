@@ -0,0 +1,173 @@
+//! Per interrupt statistics and handler latency measurement.
+//!
+//! Every PLIC source dispatched by `machine_external_isr` and the `MachineTimer`
+//! core interrupt count how often they fire, how many `mcycle` cycles their
+//! handler took in total and at most, and the `mtime` of the last time they
+//! fired.
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use heapless::Vec;
+use riscv::register::mcycle;
+
+use crate::{mono, plic::NUMBER_INTERRUPTS, println};
+
+/// Maximum number of sources held by a [Snapshot].  Only sources that fired at
+/// least once are included.
+pub const MAX_SNAPSHOT_SOURCES: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    /// PLIC source by interrupt number
+    External(u32),
+    MachineTimer,
+}
+
+/// Statistics of one interrupt source
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Stats {
+    pub count: u64,
+    pub total_cycles: u64,
+    pub max_cycles: u64,
+    /// mtime of the last time the source fired
+    pub last_timestamp: u64,
+}
+
+impl Stats {
+    /// Average handler duration in cycles
+    pub fn average_cycles(&self) -> u64 {
+        match self.count {
+            0 => 0,
+            count => self.total_cycles / count,
+        }
+    }
+}
+
+struct Counters {
+    count: AtomicU64,
+    total_cycles: AtomicU64,
+    max_cycles: AtomicU64,
+    last_timestamp: AtomicU64,
+}
+
+impl Counters {
+    const NEW: Counters = Counters {
+        count: AtomicU64::new(0),
+        total_cycles: AtomicU64::new(0),
+        max_cycles: AtomicU64::new(0),
+        last_timestamp: AtomicU64::new(0),
+    };
+
+    fn load(&self) -> Stats {
+        Stats {
+            count: self.count.load(Ordering::Relaxed),
+            total_cycles: self.total_cycles.load(Ordering::Relaxed),
+            max_cycles: self.max_cycles.load(Ordering::Relaxed),
+            last_timestamp: self.last_timestamp.load(Ordering::Relaxed),
+        }
+    }
+
+    fn clear(&self) {
+        self.count.store(0, Ordering::Relaxed);
+        self.total_cycles.store(0, Ordering::Relaxed);
+        self.max_cycles.store(0, Ordering::Relaxed);
+        self.last_timestamp.store(0, Ordering::Relaxed);
+    }
+}
+
+static EXTERNAL: [Counters; NUMBER_INTERRUPTS] = [Counters::NEW; NUMBER_INTERRUPTS];
+static MACHINE_TIMER: Counters = Counters::NEW;
+
+fn counters(source: Source) -> Option<&'static Counters> {
+    match source {
+        Source::External(n) => EXTERNAL.get(n as usize),
+        Source::MachineTimer => Some(&MACHINE_TIMER),
+    }
+}
+
+/// Starts measuring a handler.  Pass the result to [record] once the handler
+/// returned.
+#[inline]
+pub fn start() -> u64 {
+    mcycle::read64()
+}
+
+/// Records one run of the source's handler that started at `start_cycles`
+pub fn record(source: Source, start_cycles: u64) {
+    let cycles = mcycle::read64().wrapping_sub(start_cycles);
+    let Some(counters) = counters(source) else {
+        return;
+    };
+    counters.count.fetch_add(1, Ordering::Relaxed);
+    counters.total_cycles.fetch_add(cycles, Ordering::Relaxed);
+    counters.max_cycles.fetch_max(cycles, Ordering::Relaxed);
+    counters
+        .last_timestamp
+        .store(mono::ticks(), Ordering::Relaxed);
+}
+
+/// Gets the statistics of a single source
+pub fn stats(source: Source) -> Stats {
+    counters(source).map(Counters::load).unwrap_or_default()
+}
+
+/// Statistics of every source that fired at least once
+pub struct Snapshot {
+    pub machine_timer: Stats,
+    pub external: Vec<(u32, Stats), MAX_SNAPSHOT_SOURCES>,
+    /// Number of sources that fired but didn't fit in `external`
+    pub dropped: usize,
+}
+
+/// Takes a snapshot of the statistics
+pub fn snapshot() -> Snapshot {
+    let mut snapshot = Snapshot {
+        machine_timer: MACHINE_TIMER.load(),
+        external: Vec::new(),
+        dropped: 0,
+    };
+    for (n, counters) in EXTERNAL.iter().enumerate() {
+        let stats = counters.load();
+        if stats.count != 0 && snapshot.external.push((n as u32, stats)).is_err() {
+            snapshot.dropped += 1;
+        }
+    }
+    snapshot
+}
+
+/// Clears the statistics of every source
+pub fn reset() {
+    MACHINE_TIMER.clear();
+    for counters in EXTERNAL.iter() {
+        counters.clear();
+    }
+}
+
+/// Prints the snapshot as a table over the logger
+pub fn print_table(snapshot: &Snapshot) {
+    println!("Source      Count    AvgCycles    MaxCycles          LastMtime");
+    print_row("MTimer", &snapshot.machine_timer);
+    for (n, stats) in snapshot.external.iter() {
+        println!(
+            "Ext {:<6} {:>6} {:>12} {:>12} {:>18}",
+            n,
+            stats.count,
+            stats.average_cycles(),
+            stats.max_cycles,
+            stats.last_timestamp
+        );
+    }
+    if snapshot.dropped != 0 {
+        println!("{} more sources not shown", snapshot.dropped);
+    }
+}
+
+fn print_row(name: &str, stats: &Stats) {
+    println!(
+        "{:<10} {:>6} {:>12} {:>12} {:>18}",
+        name,
+        stats.count,
+        stats.average_cycles(),
+        stats.max_cycles,
+        stats.last_timestamp
+    );
+}
@@ -0,0 +1,181 @@
+//! Interrupt storm and spurious source protection for `machine_external_isr`.
+//!
+//! Every claim of a source is counted in a window of `mtime` ticks.  A source
+//! that is claimed more often than the configured limit within one window is
+//! masked on every context it was enabled on and recorded, so a misconfigured
+//! edge that is never cleared can't lock up the hart.  A masked source stays off
+//! until [rearm] is called.
+//!
+//! Nothing is printed from the interrupt.  The main loop watches
+//! [masked_count] and calls [report] when it changed:
+//!
+//! ```ignore
+//! if interrupt_storm::masked_count() != reported {
+//!     reported = interrupt_storm::masked_count();
+//!     interrupt_storm::report();
+//! }
+//! ```
+//!
+//! Once the cause is fixed, `interrupt_storm::rearm(Interrupt::SYS_IOMUX)`
+//! turns the source back on.  [set_limit] tunes the threshold for sources
+//! that legitimately fire faster than [DEFAULT_MAX_CLAIMS] a second.
+//!
+//! Claims of id 0 and of ids the dispatch table does not know about are counted
+//! as spurious.
+use core::sync::atomic::{AtomicU16, AtomicU32, AtomicU64, Ordering};
+
+use jh7110_pac as pac;
+
+use crate::{
+    mono,
+    plic::{contexts, Plic, NUMBER_INTERRUPTS},
+    println,
+};
+
+/// Default number of claims allowed per window
+pub const DEFAULT_MAX_CLAIMS: u32 = 1000;
+/// Default window length, one second of mtime
+pub const DEFAULT_WINDOW_TICKS: u64 = mono::MTIME_HZ;
+
+static MAX_CLAIMS: AtomicU32 = AtomicU32::new(DEFAULT_MAX_CLAIMS);
+static WINDOW_TICKS: AtomicU64 = AtomicU64::new(DEFAULT_WINDOW_TICKS);
+
+/// Claim rate tracking of a single source
+struct SourceRate {
+    /// mtime at the start of the current window
+    window_start: AtomicU64,
+    /// Claims seen in the current window
+    claims: AtomicU32,
+    /// Bit per PLIC context the source was enabled on when it got masked.  Zero
+    /// while the source isn't masked.
+    masked_contexts: AtomicU16,
+}
+
+impl SourceRate {
+    const NEW: SourceRate = SourceRate {
+        window_start: AtomicU64::new(0),
+        claims: AtomicU32::new(0),
+        masked_contexts: AtomicU16::new(0),
+    };
+}
+
+static RATES: [SourceRate; NUMBER_INTERRUPTS] = [SourceRate::NEW; NUMBER_INTERRUPTS];
+static SPURIOUS: AtomicU32 = AtomicU32::new(0);
+/// Most recently masked source, 0 if none
+static LAST_MASKED: AtomicU32 = AtomicU32::new(0);
+/// Number of times a source got masked
+static MASKED_COUNT: AtomicU32 = AtomicU32::new(0);
+
+/// Sets the number of claims a source may have within a window of
+/// `window_ticks` mtime ticks before it gets masked.
+pub fn set_limit(max_claims: u32, window_ticks: u64) {
+    MAX_CLAIMS.store(max_claims, Ordering::Relaxed);
+    WINDOW_TICKS.store(window_ticks, Ordering::Relaxed);
+}
+
+/// Counts a claim of the source.  Returns `false` when the source exceeded its
+/// claim rate and got masked, its handler should not run.
+pub fn track_claim(interrupt_number: u32) -> bool {
+    let Some(rate) = RATES.get(interrupt_number as usize) else {
+        record_spurious();
+        return false;
+    };
+
+    let now = mono::ticks();
+    let window_start = rate.window_start.load(Ordering::Relaxed);
+    if now.wrapping_sub(window_start) >= WINDOW_TICKS.load(Ordering::Relaxed) {
+        //Start a new window with this claim
+        rate.window_start.store(now, Ordering::Relaxed);
+        rate.claims.store(1, Ordering::Relaxed);
+        return true;
+    }
+
+    let claims = rate.claims.fetch_add(1, Ordering::Relaxed) + 1;
+    if claims <= MAX_CLAIMS.load(Ordering::Relaxed) {
+        return true;
+    }
+
+    //Recorded only, the UART is far too slow to print from here
+    mask(interrupt_number, rate);
+    false
+}
+
+/// Counts a claim of id 0 or of a source without a handler
+pub fn record_spurious() {
+    SPURIOUS.fetch_add(1, Ordering::Relaxed);
+}
+
+fn mask(interrupt_number: u32, rate: &SourceRate) {
+    let plic = Plic::new();
+    let mut masked_contexts = 0u16;
+    for (_, _, context) in contexts() {
+        if plic.is_enabled_number(context, interrupt_number) {
+            plic.disable_number(context, interrupt_number);
+            masked_contexts |= 1 << context.index();
+        }
+    }
+    rate.masked_contexts
+        .fetch_or(masked_contexts, Ordering::Relaxed);
+    LAST_MASKED.store(interrupt_number, Ordering::Relaxed);
+    MASKED_COUNT.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Re-enables a masked source on the contexts it was enabled on before it got
+/// masked and starts a fresh window.  Returns `false` if the source wasn't
+/// masked.
+pub fn rearm(interrupt: pac::Interrupt) -> bool {
+    let rate = &RATES[interrupt as usize];
+    let masked_contexts = rate.masked_contexts.swap(0, Ordering::Relaxed);
+    if masked_contexts == 0 {
+        return false;
+    }
+
+    rate.claims.store(0, Ordering::Relaxed);
+    rate.window_start.store(mono::ticks(), Ordering::Relaxed);
+    let plic = Plic::new();
+    for (_, _, context) in contexts() {
+        if masked_contexts & (1 << context.index()) != 0 {
+            plic.enable(context, interrupt);
+        }
+    }
+    true
+}
+
+/// Checks if the source was masked because of an interrupt storm
+pub fn is_masked(interrupt_number: u32) -> bool {
+    RATES
+        .get(interrupt_number as usize)
+        .is_some_and(|rate| rate.masked_contexts.load(Ordering::Relaxed) != 0)
+}
+
+/// Gets the most recently masked source
+pub fn last_masked() -> Option<u32> {
+    match LAST_MASKED.load(Ordering::Relaxed) {
+        0 => None,
+        n => Some(n),
+    }
+}
+
+/// Gets the number of times a source got masked, changes whenever there is
+/// something new to [report]
+pub fn masked_count() -> u32 {
+    MASKED_COUNT.load(Ordering::Relaxed)
+}
+
+/// Gets the number of spurious claims
+pub fn spurious_count() -> u32 {
+    SPURIOUS.load(Ordering::Relaxed)
+}
+
+/// Prints the masked sources and the spurious claim count
+pub fn report() {
+    println!("Spurious claims: {}", spurious_count());
+    for i in 1..NUMBER_INTERRUPTS as u32 {
+        if is_masked(i) {
+            println!("Masked source: {}", i);
+        }
+    }
+    if let Some(n) = last_masked() {
+        println!("Last masked source: {}", n);
+    }
+}
@@ -0,0 +1,135 @@
+//! Inter-processor interrupts through the CLINT machine software interrupt.
+//!
+//! Writing 1 to a hart's `msip` register raises its `MachineSoft` interrupt.
+//! Every hart has a single slot mailbox, [post] puts a [Message] in the target's
+//! mailbox before raising the interrupt and [machine_soft_isr] takes it out and
+//! acts on it after clearing `msip`.
+//!
+//! Harts other than hart 1 wait in `mp_hook` until [start_harts] wakes them.
+//! A hart counts as online once it reached its run loop with interrupts on,
+//! only then it handles messages.
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use riscv::interrupt::machine::Interrupt;
+
+use crate::{
+    clint::Clint,
+    mono::{Duration, Instant},
+    plic::{HartId, HartMask, NUMBER_HARTS},
+};
+
+/// Request handed from one hart to another
+#[derive(Clone, Copy)]
+pub enum Message {
+    /// Run the function on the target hart
+    Call(fn()),
+    /// Order memory accesses and synchronize the instruction cache with stores
+    /// other harts made
+    Flush,
+    /// Turn interrupts off and park the hart in `wfi` for good
+    Shutdown,
+}
+
+const EMPTY_MAILBOX: spin::Mutex<Option<Message>> = spin::Mutex::new(None);
+static MAILBOXES: [spin::Mutex<Option<Message>>; NUMBER_HARTS] = [EMPTY_MAILBOX; NUMBER_HARTS];
+/// Harts that reached their run loop, as [HartMask] bits
+static ONLINE: AtomicU8 = AtomicU8::new(0);
+
+/// Marks the hart executing this code online.  Called once it takes
+/// interrupts, right before its run loop.
+pub fn set_online() {
+    ONLINE.fetch_or(HartMask::from(HartId::current()).bits(), Ordering::Release);
+}
+
+/// Harts that reached their run loop
+pub fn online() -> HartMask {
+    HartMask::from_bits_truncate(ONLINE.load(Ordering::Acquire))
+}
+
+/// Wakes the harts parked in `mp_hook` and waits for them to come online.
+/// Returns the harts that didn't within `timeout`.
+pub fn start_harts(harts: HartMask, timeout: Duration) -> HartMask {
+    send_mask(harts);
+    let start = Instant::now();
+    while !online().contains(harts) && start.elapsed() < timeout {
+        core::hint::spin_loop();
+    }
+    harts.difference(online())
+}
+
+/// Raises the software interrupt of the hart
+pub fn send(hart: HartId) {
+    Clint::new().set_msip(hart);
+}
+
+/// Raises the software interrupt of every hart in the set
+pub fn send_mask(harts: HartMask) {
+    for hart in harts.harts() {
+        send(hart);
+    }
+}
+
+/// Clears the software interrupt of the hart executing this code
+pub fn clear() {
+    Clint::new().clear_msip(HartId::current());
+}
+
+/// Checks if the software interrupt of the hart is raised
+pub fn is_pending(hart: HartId) -> bool {
+    Clint::new().is_msip_pending(hart)
+}
+
+/// Puts the message in the hart's mailbox and raises its software interrupt.
+/// Fails with the message if the mailbox still holds one that wasn't handled.
+pub fn post(hart: HartId, message: Message) -> Result<(), Message> {
+    riscv::interrupt::free(|| {
+        let mut mailbox = MAILBOXES[hart as usize].lock();
+        match *mailbox {
+            Some(_) => Err(message),
+            None => {
+                *mailbox = Some(message);
+                Ok(())
+            }
+        }
+    })?;
+    send(hart);
+    Ok(())
+}
+
+/// Posts the message to every hart in the set.  Harts whose mailbox is full are
+/// skipped and returned.
+pub fn post_mask(harts: HartMask, message: Message) -> HartMask {
+    let mut busy = HartMask::empty();
+    for hart in harts.harts() {
+        if post(hart, message).is_err() {
+            busy |= HartMask::from(hart);
+        }
+    }
+    busy
+}
+
+#[riscv_rt::core_interrupt(Interrupt::MachineSoft)]
+fn machine_soft_isr() {
+    //Clear first so a message posted while handling this one raises it again
+    clear();
+    let message = MAILBOXES[HartId::current() as usize].lock().take();
+    match message {
+        Some(Message::Call(f)) => f(),
+        Some(Message::Flush) => unsafe {
+            core::arch::asm!("fence rw, rw", "fence.i");
+        },
+        Some(Message::Shutdown) => halt(),
+        //Plain wake up without a message
+        None => {}
+    }
+}
+
+/// Turns interrupts off on the hart executing this code and parks it
+pub fn halt() -> ! {
+    unsafe {
+        riscv::register::mstatus::clear_mie();
+    }
+    loop {
+        riscv::asm::wfi();
+    }
+}
@@ -0,0 +1,13 @@
+//! Hardware independent modules of the firmware.
+//!
+//! The firmware is the `vf2-riscv-rt` binary, which declares these modules
+//! itself.  This library only exists so their tests build and run on the host:
+//!
+//! ```text
+//! cargo test --lib --target x86_64-unknown-linux-gnu
+//! ```
+#![cfg_attr(not(test), no_std)]
+
+pub mod debouncer;
+pub mod mono;
+pub mod plic;
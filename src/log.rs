@@ -77,11 +77,123 @@ pub fn print(args: fmt::Arguments) {
     //    }
     //});
 
-    if let Some(l) = LOGGER.lock().as_mut() {
-        l.write_fmt(args).ok();
+    //An interrupt on this hart printing while the lock is held would spin on
+    //it forever, keep them off until the lock is released
+    riscv::interrupt::free(|| {
+        if let Some(l) = LOGGER.lock().as_mut() {
+            Tee {
+                logger: l,
+                history: &mut HISTORY.lock(),
+            }
+            .write_fmt(args)
+            .ok();
+        }
+    });
+}
+
+/// Number of most recently printed bytes kept by [HISTORY]
+pub const HISTORY_SIZE: usize = 512;
+
+/// Ring buffer holding the tail of everything printed through [print]
+struct History {
+    buffer: [u8; HISTORY_SIZE],
+    /// Index the next byte is written to
+    next: usize,
+    /// Set once the buffer wrapped around
+    full: bool,
+}
+
+impl History {
+    fn push(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.buffer[self.next] = byte;
+            self.next = (self.next + 1) % HISTORY_SIZE;
+            self.full |= self.next == 0;
+        }
+    }
+
+    /// Copies the most recent bytes, oldest first, into `out`.  Returns the
+    /// number of bytes copied.
+    fn copy_to(&self, out: &mut [u8]) -> usize {
+        let length = match self.full {
+            true => HISTORY_SIZE,
+            false => self.next,
+        }
+        .min(out.len());
+        let start = (self.next + HISTORY_SIZE - length) % HISTORY_SIZE;
+        for (i, byte) in out[..length].iter_mut().enumerate() {
+            *byte = self.buffer[(start + i) % HISTORY_SIZE];
+        }
+        length
+    }
+}
+
+static HISTORY: spin::Mutex<History> = spin::Mutex::new(History {
+    buffer: [0; HISTORY_SIZE],
+    next: 0,
+    full: false,
+});
+
+/// Writes to the logger and keeps a copy in the history
+struct Tee<'a> {
+    logger: &'a mut Logger,
+    history: &'a mut History,
+}
+
+impl fmt::Write for Tee<'_> {
+    fn write_str(&mut self, s: &str) -> Result<(), fmt::Error> {
+        self.history.push(s.as_bytes());
+        fmt::Write::write_str(self.logger, s)
     }
 }
 
+/// Copies the tail of the printed output, oldest byte first, into `out` and
+/// returns the number of bytes copied.  Returns 0 without waiting if the
+/// history is locked, e.g. by the context that faulted while printing.
+pub fn copy_history(out: &mut [u8]) -> usize {
+    match HISTORY.try_lock() {
+        Some(history) => history.copy_to(out),
+        None => 0,
+    }
+}
+
+/// UART0 transmit holding register
+const UART0_THR: usize = 0x1000_0000;
+/// UART0 line status register, registers are 4 bytes apart
+const UART0_LSR: usize = 0x1000_0014;
+/// Transmit holding register empty
+const UART0_LSR_THRE: u32 = 1 << 5;
+
+/// Writes straight to the UART0 registers, bypassing [LOGGER].  Only meant for
+/// when the logger can't be locked.
+struct RawUart;
+
+impl fmt::Write for RawUart {
+    fn write_str(&mut self, s: &str) -> Result<(), fmt::Error> {
+        for &byte in s.as_bytes() {
+            unsafe {
+                while core::ptr::read_volatile(UART0_LSR as *const u32) & UART0_LSR_THRE == 0 {}
+                core::ptr::write_volatile(UART0_THR as *mut u32, byte as u32);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Prints without ever waiting on [LOGGER].  If the logger is held, for example
+/// by the context that panicked, the output goes straight to the UART registers.
+pub fn print_force(args: fmt::Arguments) {
+    use fmt::Write;
+
+    match LOGGER.try_lock() {
+        Some(mut logger) => match logger.as_mut() {
+            Some(l) => l.write_fmt(args).ok(),
+            None => RawUart.write_fmt(args).ok(),
+        },
+        None => RawUart.write_fmt(args).ok(),
+    };
+}
+
 /// Serial implementation of the `print` macro from `core`.
 ///
 /// From [`oreboot`](https://github.com/oreboot/oreboot/blob/37a5e71b3095922aedbe4c40fe2a7a68595a3198/src/lib/log/src/lib.rs)
@@ -1,28 +1,48 @@
 #![no_std]
 #![no_main]
 
-extern crate panic_halt;
-
 mod array_vec;
 mod blinky;
 mod blinky_pwm;
+mod clint;
+mod crash;
+mod debouncer;
 mod default_isr_this_has_to_be_wrong;
+mod exception;
 mod init;
 mod input_signal;
+mod interrupt_stats;
+mod interrupt_storm;
+mod ipi;
 mod log;
+mod mono;
+mod panic;
+mod plic;
+mod soft_timer;
 mod stepper_motor;
 mod timer;
+mod watchdog;
 
 use riscv_rt::{entry, pre_init};
 
+use crate::{
+    mono::{Duration, Instant},
+    plic::HartMask,
+};
+
 #[export_name = "_mp_hook"]
 pub extern "Rust" fn mp_hook(hartid: usize) -> bool {
-    //If hart is 1 return true, otherwise spin.
-    //TODO: if later on we want to bring another hart online we will have to
-    //modify loop to break out.
+    //If hart is 1 return true, otherwise spin until another hart wakes this
+    //one with ipi::send().  msip is left raised so machine_soft_isr picks up a
+    //posted message once secondary_main enabled interrupts.
     match hartid {
         1 => true,
         _ => {
+            //wfi only resumes for a locally enabled interrupt.  mstatus.MIE
+            //stays off, so the raised msip wakes the hart without trapping.
+            unsafe {
+                riscv::register::mie::set_msoft();
+            }
             loop {
                 riscv::asm::wfi();
                 if riscv::register::mip::read().msoft() {
@@ -34,12 +54,6 @@ pub extern "Rust" fn mp_hook(hartid: usize) -> bool {
     }
 }
 
-#[export_name = "ExceptionHandler"]
-fn custom_exception_handler(trap_frame: &riscv_rt::TrapFrame) -> ! {
-    println!("exception {:?}", trap_frame);
-    loop {}
-}
-
 #[export_name = "DefaultHandler"]
 fn custom_default_handler() {
     println!("custom_default_handler()");
@@ -78,28 +92,41 @@ impl From<usize> for Harts {
     }
 }
 
+/// Harts woken at boot.  Hart 0 is the S7 monitor core, it lacks the FPU this
+/// binary is built for.
+const SECONDARY_HARTS: HartMask = HartMask::HART2
+    .union(HartMask::HART3)
+    .union(HartMask::HART4);
+/// How long hart 1 waits for the secondary harts to come online
+const SECONDARY_BOOT_TIMEOUT: Duration = Duration::from_millis(100);
+/// How often the main loop prints the interrupt statistics
+const STATS_PERIOD: Duration = Duration::from_secs(10);
+/// The SoC resets if the main loop doesn't come around for this long
+const WATCHDOG_TIMEOUT: Duration = Duration::from_secs(2);
+
 #[entry]
 fn main() -> ! {
-    log::init();
-
     let hart_id = Harts::from(riscv::register::mhartid::read());
-    match hart_id {
-        Harts::Hart0 => init::print_ids(),
-        Harts::Hart1 => {
-            init::print_boot_mode();
-            init::print_ids();
-            unsafe {
-                init::setup_ddr();
-            }
-        }
-        Harts::Hart2 => init::print_ids(),
-        Harts::Hart3 => init::print_ids(),
-        Harts::Hart4 => init::print_ids(),
-        _ => {
-            println!("fml");
+    if !matches!(hart_id, Harts::Hart1) {
+        secondary_main();
+    }
 
-            //println!("Unknown hart {:?} booted.", hart_id);
-        }
+    log::init();
+    init::print_boot_mode();
+    init::print_ids();
+    let record = crash::take();
+    //The JH7110 has no reset cause register and the watchdog registers are
+    //reset with the SoC, only a record from its interrupt tells
+    let watchdog_reset = match &record {
+        Some(r) if r.cause() == crash::Cause::Watchdog => "yes",
+        _ => "unknown",
+    };
+    println!("Last reset came from the watchdog: {}", watchdog_reset);
+    if let Some(record) = record {
+        println!("Crash record from the previous boot:\r\n{}", record);
+    }
+    unsafe {
+        init::setup_ddr();
     }
 
     //Setup core local things
@@ -107,32 +134,97 @@ fn main() -> ! {
         init::setup_mstatus();
         init::setup_features();
     };
+    init::check_trap_vector();
+    default_isr_this_has_to_be_wrong::init_hart_context();
+    unsafe {
+        //Take inter-processor interrupts from the other harts
+        riscv::register::mie::set_msoft();
+    }
 
-    match hart_id {
-        Harts::Hart1 => {
-            default_isr_this_has_to_be_wrong::clear_interrupt_enable_all();
-            default_isr_this_has_to_be_wrong::clear_interrupt_priotiry_all();
-            blinky::configure();
-            blinky_pwm::configure();
-            input_signal::configure();
-            stepper_motor::init();
-            println!("back in main about to spin after setting up blinky");
-            init::print_uart_isr_reg();
-            //default_isr_this_has_to_be_wrong::print_interrupt_enable();
-            //default_isr_this_has_to_be_wrong::print_pending_interrupt_info();
-            //default_isr_this_has_to_be_wrong::print_priority_interrupt_info();
-            unsafe {
-                println!("Enabeling machine timer interrupt");
-                riscv::register::mie::set_mtimer();
-                println!("Enabeling machine external interrupt");
-                riscv::register::mie::set_mext();
-                println!("Enabeling interrupts");
-                riscv::register::mstatus::set_mie();
-            }
-            //input_signal::configure();
+    default_isr_this_has_to_be_wrong::clear_interrupt_enable_all();
+    default_isr_this_has_to_be_wrong::clear_interrupt_priotiry_all();
+    let cleared = plic::Plic::new().snapshot();
+    if !cleared.is_cleared() {
+        println!("PLIC not cleared\r\n{}", cleared);
+    }
+    //Bring the other harts up before drivers route sources to them
+    let offline = ipi::start_harts(SECONDARY_HARTS, SECONDARY_BOOT_TIMEOUT);
+    if !offline.is_empty() {
+        println!("Harts not online: {:?}", offline);
+    }
+    match timer::Timer1::take() {
+        Some(t1) => soft_timer::init(t1),
+        None => println!("Timer1 already taken"),
+    }
+    blinky::configure();
+    blinky_pwm::configure();
+    input_signal::configure(input_signal::DebounceMode::Timestamp);
+    stepper_motor::init();
+    println!("back in main about to spin after setting up blinky");
+    init::print_uart_isr_reg();
+    //Drivers leaving sources enabled show up in the diff
+    for change in plic::Plic::new().snapshot().diff(&cleared) {
+        println!("PLIC {}", change);
+    }
+    unsafe {
+        println!("Enabeling machine timer interrupt");
+        riscv::register::mie::set_mtimer();
+        println!("Enabeling machine external interrupt");
+        riscv::register::mie::set_mext();
+        println!("Enabeling interrupts");
+        riscv::register::mstatus::set_mie();
+    }
+    //input_signal::configure();
+    ipi::set_online();
+    //Started after the secondary harts are up so its interrupt goes to one of
+    //them, the main loop feeds it
+    match watchdog::enable(WATCHDOG_TIMEOUT) {
+        Ok(()) => watchdog::lock(),
+        Err(e) => println!("Watchdog not enabled: {:?}", e),
+    }
+
+    //Input edges, noisy inputs and masked interrupt storms are recorded by the
+    //interrupts and printed here, away from the blocking UART
+    let mut reported_storms = 0;
+    let mut stats_printed = Instant::now();
+    loop {
+        watchdog::feed();
+        while let Some(event) = input_signal::next_event() {
+            println!("Input {}", event);
+        }
+        while let Some(pad) = input_signal::next_noisy() {
+            println!("Input {:?} is noisy, check the switch", pad);
+        }
+        if interrupt_storm::masked_count() != reported_storms {
+            reported_storms = interrupt_storm::masked_count();
+            interrupt_storm::report();
+        }
+        if stats_printed.elapsed() >= STATS_PERIOD {
+            stats_printed = Instant::now();
+            interrupt_stats::print_table(&interrupt_stats::snapshot());
         }
-        _ => {}
     }
+}
 
-    loop {}
+/// Entry of the harts [mp_hook] parked, once hart 1 woke them.  Hart 1 owns the
+/// shared setup (logger, clocks, PLIC), these only set up their own core and
+/// wait for interrupts routed to them and messages from the other harts.
+fn secondary_main() -> ! {
+    unsafe {
+        init::setup_mstatus();
+        init::setup_features();
+    };
+    init::print_ids();
+    default_isr_this_has_to_be_wrong::init_hart_context();
+    unsafe {
+        //Sources routed to this hart with enable_interrupt_on() are claimed
+        //from its own context by machine_external_isr
+        riscv::register::mie::set_msoft();
+        riscv::register::mie::set_mext();
+        riscv::register::mstatus::set_mie();
+    }
+    ipi::set_online();
+    loop {
+        riscv::asm::wfi();
+    }
 }
@@ -0,0 +1,210 @@
+//! Monotonic time base from the CLINT `mtime` counter.
+//!
+//! `mtime` is a single 64 bit counter shared by every hart, read with one load,
+//! so [Instant::now] is safe to call from any hart and from interrupt handlers.
+//! At 4MHz it wraps after more than 146000 years, so wrapping is ignored.
+use core::{
+    fmt,
+    ops::{Add, AddAssign, Sub, SubAssign},
+    ptr,
+};
+
+pub use core::time::Duration;
+
+/// CLINT machine time register
+const MTIME_ADDRESS: usize = 0x0200_bff8;
+/// Frequency mtime counts at, the 24MHz oscillator divided down to the RTC clock
+pub const MTIME_HZ: u64 = 4_000_000;
+/// Shared by everything converting between durations and clock ticks
+pub const NANOS_PER_SECOND: u64 = 1_000_000_000;
+
+/// Reads the raw mtime counter
+#[inline]
+pub fn ticks() -> u64 {
+    unsafe { ptr::read_volatile(MTIME_ADDRESS as *const u64) }
+}
+
+/// Converts the duration to mtime ticks, rounding up so a deadline computed
+/// from it is never early.  Saturates at u64::MAX.
+pub fn duration_to_ticks(duration: Duration) -> u64 {
+    let ticks = (duration.as_nanos() * MTIME_HZ as u128).div_ceil(NANOS_PER_SECOND as u128);
+    ticks.min(u64::MAX as u128) as u64
+}
+
+/// Converts mtime ticks to a duration
+pub fn ticks_to_duration(ticks: u64) -> Duration {
+    let nanos = ticks as u128 * NANOS_PER_SECOND as u128 / MTIME_HZ as u128;
+    Duration::new(
+        (nanos / NANOS_PER_SECOND as u128) as u64,
+        (nanos % NANOS_PER_SECOND as u128) as u32,
+    )
+}
+
+/// Point in time as mtime ticks since reset
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant(u64);
+
+impl Instant {
+    /// The reset of the SoC
+    pub const ZERO: Instant = Instant(0);
+
+    pub fn now() -> Self {
+        Instant(ticks())
+    }
+
+    pub const fn from_ticks(ticks: u64) -> Self {
+        Instant(ticks)
+    }
+
+    pub const fn ticks(self) -> u64 {
+        self.0
+    }
+
+    /// Time passed since `earlier`, zero if `earlier` is later than this one
+    pub fn duration_since(self, earlier: Instant) -> Duration {
+        ticks_to_duration(self.0.saturating_sub(earlier.0))
+    }
+
+    /// Time passed since this instant
+    pub fn elapsed(self) -> Duration {
+        Instant::now().duration_since(self)
+    }
+
+    pub fn checked_add(self, duration: Duration) -> Option<Instant> {
+        self.0.checked_add(duration_to_ticks(duration)).map(Instant)
+    }
+
+    pub fn checked_sub(self, duration: Duration) -> Option<Instant> {
+        self.0.checked_sub(duration_to_ticks(duration)).map(Instant)
+    }
+
+    /// Microseconds since reset
+    pub fn as_micros(self) -> u64 {
+        self.0 / (MTIME_HZ / 1_000_000)
+    }
+
+    /// Milliseconds since reset
+    pub fn as_millis(self) -> u64 {
+        self.0 / (MTIME_HZ / 1_000)
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    /// Saturates instead of overflowing
+    fn add(self, duration: Duration) -> Instant {
+        Instant(self.0.saturating_add(duration_to_ticks(duration)))
+    }
+}
+
+impl AddAssign<Duration> for Instant {
+    fn add_assign(&mut self, duration: Duration) {
+        *self = *self + duration;
+    }
+}
+
+impl Sub<Duration> for Instant {
+    type Output = Instant;
+
+    /// Saturates at [Instant::ZERO]
+    fn sub(self, duration: Duration) -> Instant {
+        Instant(self.0.saturating_sub(duration_to_ticks(duration)))
+    }
+}
+
+impl SubAssign<Duration> for Instant {
+    fn sub_assign(&mut self, duration: Duration) {
+        *self = *self - duration;
+    }
+}
+
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+
+    fn sub(self, earlier: Instant) -> Duration {
+        self.duration_since(earlier)
+    }
+}
+
+/// Seconds since reset with microsecond resolution, e.g. `12.345678s`
+impl fmt::Display for Instant {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let micros = self.as_micros();
+        write!(f, "{}.{:06}s", micros / 1_000_000, micros % 1_000_000)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn duration_to_ticks_rounds_up() {
+        assert_eq!(duration_to_ticks(Duration::ZERO), 0);
+        assert_eq!(duration_to_ticks(Duration::from_nanos(1)), 1);
+        assert_eq!(duration_to_ticks(Duration::from_nanos(250)), 1);
+        assert_eq!(duration_to_ticks(Duration::from_nanos(251)), 2);
+        assert_eq!(duration_to_ticks(Duration::from_secs(1)), 4_000_000);
+        assert_eq!(duration_to_ticks(Duration::MAX), u64::MAX);
+    }
+
+    #[test]
+    fn ticks_to_duration_round_trips() {
+        assert_eq!(ticks_to_duration(1), Duration::from_nanos(250));
+        assert_eq!(ticks_to_duration(4_000_000), Duration::from_secs(1));
+        for ticks in [0, 1, 3, 4_000_001, u32::MAX as u64] {
+            assert_eq!(duration_to_ticks(ticks_to_duration(ticks)), ticks);
+        }
+    }
+
+    #[test]
+    fn add_and_sub_duration() {
+        let start = Instant::from_ticks(10);
+        assert_eq!(start + Duration::from_micros(1), Instant::from_ticks(14));
+        assert_eq!(start + Duration::from_nanos(1), Instant::from_ticks(11));
+        assert_eq!(start - Duration::from_nanos(500), Instant::from_ticks(8));
+        let mut later = start;
+        later += Duration::from_millis(1);
+        assert_eq!(later, Instant::from_ticks(4_010));
+        later -= Duration::from_millis(1);
+        assert_eq!(later, start);
+    }
+
+    #[test]
+    fn saturates_instead_of_wrapping() {
+        let end = Instant::from_ticks(u64::MAX - 1);
+        assert_eq!(end + Duration::from_secs(1), Instant::from_ticks(u64::MAX));
+        assert_eq!(end.checked_add(Duration::from_secs(1)), None);
+        assert_eq!(
+            Instant::from_ticks(3) - Duration::from_secs(1),
+            Instant::ZERO
+        );
+        assert_eq!(
+            Instant::from_ticks(3).checked_sub(Duration::from_secs(1)),
+            None
+        );
+    }
+
+    #[test]
+    fn duration_since() {
+        let earlier = Instant::from_ticks(4_000_000);
+        let later = earlier + Duration::from_millis(1500);
+        assert_eq!(later.duration_since(earlier), Duration::from_millis(1500));
+        assert_eq!(later - earlier, Duration::from_millis(1500));
+        //An earlier instant that is actually later gives zero, not a wrapped
+        //difference
+        assert_eq!(earlier.duration_since(later), Duration::ZERO);
+        assert_eq!(
+            Instant::from_ticks(u64::MAX).duration_since(Instant::ZERO),
+            ticks_to_duration(u64::MAX)
+        );
+    }
+
+    #[test]
+    fn display() {
+        let instant = Instant::ZERO + Duration::from_micros(12_345_678);
+        assert_eq!(instant.to_string(), "12.345678s");
+        assert_eq!(Instant::ZERO.to_string(), "0.000000s");
+    }
+}
@@ -0,0 +1,100 @@
+//! Panic handler that reports over UART0.
+//!
+//! The report goes through [log::print_force] so a panic raised while the
+//! logger is locked, for example inside an ISR that interrupted a `println!`,
+//! still gets printed.  Afterwards the configured [PanicAction] is taken.
+use core::{
+    panic::PanicInfo,
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+use riscv::register::{mepc, mstatus};
+
+use crate::{
+    crash,
+    ipi::{self, Message},
+    log,
+    plic::{HartId, HartMask},
+    watchdog,
+};
+
+/// What the panic handler does after printing the report
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PanicAction {
+    /// Shut down every other hart through an IPI and park this one
+    HaltAll = 0,
+    /// Reset the SoC through the watchdog
+    Reset = 1,
+}
+
+impl From<u8> for PanicAction {
+    fn from(value: u8) -> Self {
+        match value {
+            1 => PanicAction::Reset,
+            _ => PanicAction::HaltAll,
+        }
+    }
+}
+
+static ACTION: AtomicU8 = AtomicU8::new(PanicAction::HaltAll as u8);
+/// Bit per hart that is currently panicking
+static PANICKING: AtomicU8 = AtomicU8::new(0);
+
+/// Sets what the panic handler does after printing the report
+pub fn set_action(action: PanicAction) {
+    ACTION.store(action as u8, Ordering::Relaxed);
+}
+
+pub fn action() -> PanicAction {
+    PanicAction::from(ACTION.load(Ordering::Relaxed))
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    unsafe {
+        mstatus::clear_mie();
+    }
+    let hart = HartId::current();
+    let hart_bit = 1 << hart as u8;
+    if PANICKING.fetch_or(hart_bit, Ordering::Relaxed) & hart_bit != 0 {
+        //Panicked while reporting a panic, don't try again
+        ipi::halt();
+    }
+
+    log::print_force(format_args!("\r\npanic on {:?}", hart));
+    if let Some(location) = info.location() {
+        log::print_force(format_args!(
+            " at {}:{}:{}",
+            location.file(),
+            location.line(),
+            location.column()
+        ));
+    }
+    log::print_force(format_args!("\r\n{}\r\n", info.message()));
+    log::print_force(format_args!("mepc: {:#018x}\r\n", mepc::read()));
+    match info.location() {
+        Some(location) => crash::record_panic(format_args!(
+            "{} at {}:{}",
+            info.message(),
+            location.file(),
+            location.line()
+        )),
+        None => crash::record_panic(format_args!("{}", info.message())),
+    }
+
+    match action() {
+        PanicAction::HaltAll => {
+            //Harts still parked in mp_hook would boot into secondary_main,
+            //only stop the ones that are running
+            let others = ipi::online().difference(HartMask::from(hart));
+            ipi::post_mask(others, Message::Shutdown);
+            log::print_force(format_args!("halted\r\n"));
+            ipi::halt();
+        }
+        PanicAction::Reset => {
+            log::print_force(format_args!("resetting\r\n"));
+            watchdog::reset_soc();
+        }
+    }
+}
@@ -0,0 +1,786 @@
+//! Driver for the SiFive U74-MC Platform-Level Interrupt Controller (PLIC).
+//!
+//! The PLIC exposes one interrupt "context" per hart and privilege mode.  Hart 0
+//! is the S7 monitor core and only has a machine mode context, harts 1-4 are the
+//! U74 application cores and have a machine and a supervisor mode context each:
+//!
+//! | Context | Hart | Mode       |
+//! |---------|------|------------|
+//! | 0       | 0    | Machine    |
+//! | 1       | 1    | Machine    |
+//! | 2       | 1    | Supervisor |
+//! | 3       | 2    | Machine    |
+//! | ...     | ...  | ...        |
+//! | 8       | 4    | Supervisor |
+//!
+//! Every register that is banked per context (enables, threshold and
+//! claim/complete) is computed from the context index instead of being looked up
+//! in a table.
+//!
+//! <https://sifive.cdn.prismic.io/sifive/2dd11994-693c-4360-8aea-5453d8642c42_u74mc_core_complex_manual_21G3.pdf>
+use core::{fmt, marker::PhantomData, ptr};
+
+use bitflags::bitflags;
+use jh7110_pac as pac;
+
+/// System memory map start address of the PLIC
+pub const PLIC_BASE: usize = 0x0C00_0000;
+
+/// Source priorities, one 32 bit register per interrupt source
+const PRIORITY_OFFSET: usize = 0x0000;
+/// Pending bits, one bit per interrupt source
+const PENDING_OFFSET: usize = 0x1000;
+/// Enable bits, one bit per interrupt source and banked per context
+const ENABLE_OFFSET: usize = 0x2000;
+const ENABLE_CONTEXT_STRIDE: usize = 0x80;
+/// Threshold and claim/complete registers banked per context
+const CONTEXT_OFFSET: usize = 0x20_0000;
+const CONTEXT_STRIDE: usize = 0x1000;
+const THRESHOLD_OFFSET: usize = 0x0;
+const CLAIM_COMPLETE_OFFSET: usize = 0x4;
+
+/// Number of interrupt sources including the reserved source 0
+pub const NUMBER_INTERRUPTS: usize = 137;
+/// Number of 32 bit registers needed to hold one bit per interrupt source
+pub const NUMBER_INTERRUPT_REGISTERS: usize = NUMBER_INTERRUPTS.div_ceil(32);
+/// Number of harts in the core complex
+pub const NUMBER_HARTS: usize = 5;
+/// Number of hart/mode contexts.  Hart 0 only has a machine mode context.
+pub const NUMBER_CONTEXTS: usize = 2 * NUMBER_HARTS - 1;
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InterruptPriority {
+    Disabled = 0,
+    Priority1 = 1,
+    Priority2 = 2,
+    Priority3 = 3,
+    Priority4 = 4,
+    Priority5 = 5,
+    Priority6 = 6,
+    Priority7 = 7,
+}
+
+impl From<u32> for InterruptPriority {
+    fn from(value: u32) -> Self {
+        match value {
+            0 => InterruptPriority::Disabled,
+            1 => InterruptPriority::Priority1,
+            2 => InterruptPriority::Priority2,
+            3 => InterruptPriority::Priority3,
+            4 => InterruptPriority::Priority4,
+            5 => InterruptPriority::Priority5,
+            6 => InterruptPriority::Priority6,
+            7 => InterruptPriority::Priority7,
+            _ => InterruptPriority::Disabled,
+        }
+    }
+}
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExecutionMode {
+    Machine,
+    Supervisor,
+}
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HartId {
+    Hart0,
+    Hart1,
+    Hart2,
+    Hart3,
+    Hart4,
+}
+
+impl From<usize> for HartId {
+    fn from(value: usize) -> Self {
+        match value {
+            0 => HartId::Hart0,
+            1 => HartId::Hart1,
+            2 => HartId::Hart2,
+            3 => HartId::Hart3,
+            4 => HartId::Hart4,
+            _ => HartId::Hart0,
+        }
+    }
+}
+
+impl HartId {
+    /// Gets the id of the hart that is executing this code
+    pub fn current() -> Self {
+        HartId::from(riscv::register::mhartid::read())
+    }
+}
+
+bitflags! {
+    /// Set of harts an interrupt source is routed to
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct HartMask: u8 {
+        const HART0 = 1 << 0;
+        const HART1 = 1 << 1;
+        const HART2 = 1 << 2;
+        const HART3 = 1 << 3;
+        const HART4 = 1 << 4;
+    }
+}
+
+impl From<HartId> for HartMask {
+    fn from(hart: HartId) -> Self {
+        HartMask::from_bits_truncate(1 << hart as u8)
+    }
+}
+
+impl HartMask {
+    /// Iterates over the harts in the set
+    pub fn harts(self) -> impl Iterator<Item = HartId> {
+        (0..NUMBER_HARTS)
+            .filter(move |&i| self.bits() & (1 << i) != 0)
+            .map(HartId::from)
+    }
+}
+
+/// A PLIC interrupt context, the pairing of a hart and an execution mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Context(usize);
+
+impl Context {
+    /// Computes the context for the hart and mode.  Returns `None` for the
+    /// supervisor mode of hart 0, which does not exist.
+    pub const fn new(hart: HartId, mode: ExecutionMode) -> Option<Self> {
+        match (hart, mode) {
+            (HartId::Hart0, ExecutionMode::Machine) => Some(Self(0)),
+            (HartId::Hart0, ExecutionMode::Supervisor) => None,
+            (hart, ExecutionMode::Machine) => Some(Self(2 * hart as usize - 1)),
+            (hart, ExecutionMode::Supervisor) => Some(Self(2 * hart as usize)),
+        }
+    }
+
+    /// Machine mode context of the hart executing this code
+    pub fn current_machine() -> Self {
+        // Machine mode exists on every hart
+        Self(match HartId::current() {
+            HartId::Hart0 => 0,
+            hart => 2 * hart as usize - 1,
+        })
+    }
+
+    /// Raw context index
+    pub const fn index(&self) -> usize {
+        self.0
+    }
+
+    /// Hart the context belongs to
+    pub fn hart(&self) -> HartId {
+        HartId::from(self.0.div_ceil(2))
+    }
+
+    /// Execution mode the context belongs to
+    pub fn mode(&self) -> ExecutionMode {
+        match self.0 {
+            0 => ExecutionMode::Machine,
+            i if i % 2 == 1 => ExecutionMode::Machine,
+            _ => ExecutionMode::Supervisor,
+        }
+    }
+
+    /// Address of the first enable register of this context
+    pub const fn enable_address(&self) -> usize {
+        PLIC_BASE + ENABLE_OFFSET + ENABLE_CONTEXT_STRIDE * self.0
+    }
+
+    /// Address of the priority threshold register of this context
+    pub const fn threshold_address(&self) -> usize {
+        PLIC_BASE + CONTEXT_OFFSET + CONTEXT_STRIDE * self.0 + THRESHOLD_OFFSET
+    }
+
+    /// Address of the claim/complete register of this context
+    pub const fn claim_complete_address(&self) -> usize {
+        PLIC_BASE + CONTEXT_OFFSET + CONTEXT_STRIDE * self.0 + CLAIM_COMPLETE_OFFSET
+    }
+}
+
+/// Iterates over every hart/mode context of the PLIC
+pub fn contexts() -> impl Iterator<Item = (HartId, ExecutionMode, Context)> {
+    [
+        HartId::Hart0,
+        HartId::Hart1,
+        HartId::Hart2,
+        HartId::Hart3,
+        HartId::Hart4,
+    ]
+    .into_iter()
+    .flat_map(|hart| {
+        [ExecutionMode::Machine, ExecutionMode::Supervisor]
+            .into_iter()
+            .filter_map(move |mode| Context::new(hart, mode).map(|c| (hart, mode, c)))
+    })
+}
+
+impl fmt::Display for Context {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}/{:?}", self.hart(), self.mode())
+    }
+}
+
+/// Address of the priority register of an interrupt source
+pub const fn priority_address(interrupt_number: u32) -> usize {
+    PLIC_BASE + PRIORITY_OFFSET + 4 * interrupt_number as usize
+}
+
+/// Address of the pending register holding the bit of an interrupt source
+pub const fn pending_address(interrupt_number: u32) -> usize {
+    PLIC_BASE + PENDING_OFFSET + 4 * (interrupt_number as usize / 32)
+}
+
+/// Address of the enable register holding the bit of an interrupt source for a
+/// context
+pub const fn enable_address(context: Context, interrupt_number: u32) -> usize {
+    context.enable_address() + 4 * (interrupt_number as usize / 32)
+}
+
+/// Bit mask of an interrupt source within its pending/enable register
+pub const fn bit_mask(interrupt_number: u32) -> u32 {
+    1 << (interrupt_number % 32)
+}
+
+/// Struct to access the PLIC
+pub struct Plic {
+    _marker: PhantomData<*const ()>,
+}
+
+impl Default for Plic {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Plic {
+    pub fn new() -> Self {
+        Self {
+            _marker: PhantomData,
+        }
+    }
+
+    /// Enables the interrupt source for the context
+    pub fn enable(&self, context: Context, interrupt: pac::Interrupt) {
+        self.enable_number(context, interrupt as u32);
+    }
+
+    /// Enables the interrupt source by its raw number
+    pub fn enable_number(&self, context: Context, interrupt_number: u32) {
+        let reg = enable_address(context, interrupt_number);
+        unsafe {
+            let value = ptr::read_volatile(reg as *const u32);
+            ptr::write_volatile(reg as *mut u32, value | bit_mask(interrupt_number));
+        }
+    }
+
+    /// Disables the interrupt source for the context
+    pub fn disable(&self, context: Context, interrupt: pac::Interrupt) {
+        self.disable_number(context, interrupt as u32);
+    }
+
+    /// Disables the interrupt source by its raw number.  Used for claimed ids
+    /// that do not map to a [pac::Interrupt].
+    pub fn disable_number(&self, context: Context, interrupt_number: u32) {
+        let reg = enable_address(context, interrupt_number);
+        unsafe {
+            let value = ptr::read_volatile(reg as *const u32);
+            ptr::write_volatile(reg as *mut u32, value & !bit_mask(interrupt_number));
+        }
+    }
+
+    /// Disables every interrupt source for the context
+    pub fn disable_all(&self, context: Context) {
+        for i in 0..NUMBER_INTERRUPT_REGISTERS {
+            unsafe {
+                ptr::write_volatile((context.enable_address() + 4 * i) as *mut u32, 0);
+            }
+        }
+    }
+
+    /// Checks if the interrupt source is enabled for the context
+    pub fn is_enabled(&self, context: Context, interrupt: pac::Interrupt) -> bool {
+        self.is_enabled_number(context, interrupt as u32)
+    }
+
+    /// Checks if the interrupt source is enabled for the context by its raw
+    /// number
+    pub fn is_enabled_number(&self, context: Context, interrupt_number: u32) -> bool {
+        let value =
+            unsafe { ptr::read_volatile(enable_address(context, interrupt_number) as *const u32) };
+        value & bit_mask(interrupt_number) != 0
+    }
+
+    /// Checks if the interrupt source is pending
+    pub fn is_pending(&self, interrupt: pac::Interrupt) -> bool {
+        let n = interrupt as u32;
+        let value = unsafe { ptr::read_volatile(pending_address(n) as *const u32) };
+        value & bit_mask(n) != 0
+    }
+
+    /// GET/SET the priority of an interrupt source.  A priority of
+    /// [InterruptPriority::Disabled] never interrupts.
+    pub fn priority(&self, interrupt_number: u32) -> InterruptPriority {
+        let value = unsafe { ptr::read_volatile(priority_address(interrupt_number) as *const u32) };
+        InterruptPriority::from(value)
+    }
+
+    pub fn set_priority(&self, interrupt: pac::Interrupt, priority: InterruptPriority) {
+        self.set_priority_number(interrupt as u32, priority);
+    }
+
+    /// Sets the priority of an interrupt source by its raw number
+    pub fn set_priority_number(&self, interrupt_number: u32, priority: InterruptPriority) {
+        unsafe {
+            ptr::write_volatile(
+                priority_address(interrupt_number) as *mut u32,
+                priority as u32,
+            );
+        }
+    }
+
+    /// GET/SET the priority threshold of a context.  Only sources with a
+    /// priority greater than the threshold interrupt the context.
+    pub fn threshold(&self, context: Context) -> InterruptPriority {
+        let value = unsafe { ptr::read_volatile(context.threshold_address() as *const u32) };
+        InterruptPriority::from(value)
+    }
+
+    pub fn set_threshold(&self, context: Context, threshold: InterruptPriority) {
+        unsafe {
+            ptr::write_volatile(context.threshold_address() as *mut u32, threshold as u32);
+        }
+    }
+
+    /// Claims the highest priority pending interrupt for the context.  Returns
+    /// 0 when there is nothing to claim.
+    pub fn claim(&self, context: Context) -> u32 {
+        unsafe { ptr::read_volatile(context.claim_complete_address() as *const u32) }
+    }
+
+    /// Signals the PLIC that the handler of a claimed interrupt has completed
+    pub fn complete(&self, context: Context, interrupt_number: u32) {
+        unsafe {
+            ptr::write_volatile(
+                context.claim_complete_address() as *mut u32,
+                interrupt_number,
+            );
+        }
+    }
+
+    /// Reads the complete state of the PLIC
+    pub fn snapshot(&self) -> PlicSnapshot {
+        let mut snapshot = PlicSnapshot {
+            priorities: [0; NUMBER_INTERRUPTS],
+            pending: [0; NUMBER_INTERRUPT_REGISTERS],
+            enables: [[0; NUMBER_INTERRUPT_REGISTERS]; NUMBER_CONTEXTS],
+            thresholds: [0; NUMBER_CONTEXTS],
+        };
+        for n in 1..NUMBER_INTERRUPTS as u32 {
+            snapshot.priorities[n as usize] = self.priority(n) as u8;
+        }
+        for i in 0..NUMBER_INTERRUPT_REGISTERS {
+            snapshot.pending[i] =
+                unsafe { ptr::read_volatile(pending_address(32 * i as u32) as *const u32) };
+        }
+        for (_, _, context) in contexts() {
+            for i in 0..NUMBER_INTERRUPT_REGISTERS {
+                snapshot.enables[context.index()][i] = unsafe {
+                    ptr::read_volatile(enable_address(context, 32 * i as u32) as *const u32)
+                };
+            }
+            snapshot.thresholds[context.index()] = self.threshold(context) as u8;
+        }
+        snapshot
+    }
+}
+
+/// Copy of the PLIC state: source priorities, pending bits and the enables and
+/// threshold of every context.
+#[derive(Clone, PartialEq, Eq)]
+pub struct PlicSnapshot {
+    pub priorities: [u8; NUMBER_INTERRUPTS],
+    pub pending: [u32; NUMBER_INTERRUPT_REGISTERS],
+    pub enables: [[u32; NUMBER_INTERRUPT_REGISTERS]; NUMBER_CONTEXTS],
+    pub thresholds: [u8; NUMBER_CONTEXTS],
+}
+
+/// A single difference between two [PlicSnapshot]s
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlicChange {
+    Priority {
+        interrupt: u32,
+        old: u8,
+        new: u8,
+    },
+    Pending {
+        interrupt: u32,
+        pending: bool,
+    },
+    Enable {
+        context: Context,
+        interrupt: u32,
+        enabled: bool,
+    },
+    Threshold {
+        context: Context,
+        old: u8,
+        new: u8,
+    },
+}
+
+impl fmt::Display for PlicChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            PlicChange::Priority {
+                interrupt,
+                old,
+                new,
+            } => {
+                write!(f, "priority {interrupt}: {old} -> {new}")
+            }
+            PlicChange::Pending { interrupt, pending } => {
+                write!(f, "pending {interrupt}: {pending}")
+            }
+            PlicChange::Enable {
+                context,
+                interrupt,
+                enabled,
+            } => write!(f, "enable {context} {interrupt}: {enabled}"),
+            PlicChange::Threshold { context, old, new } => {
+                write!(f, "threshold {context}: {old} -> {new}")
+            }
+        }
+    }
+}
+
+fn bit(bits: &[u32; NUMBER_INTERRUPT_REGISTERS], interrupt_number: u32) -> bool {
+    bits[interrupt_number as usize / 32] & bit_mask(interrupt_number) != 0
+}
+
+impl PlicSnapshot {
+    /// Checks if the source is pending
+    pub fn is_pending(&self, interrupt_number: u32) -> bool {
+        bit(&self.pending, interrupt_number)
+    }
+
+    /// Checks if the source is enabled for the context
+    pub fn is_enabled(&self, context: Context, interrupt_number: u32) -> bool {
+        bit(&self.enables[context.index()], interrupt_number)
+    }
+
+    /// Iterates over the sources enabled for the context
+    pub fn enabled(&self, context: Context) -> impl Iterator<Item = u32> + '_ {
+        (1..NUMBER_INTERRUPTS as u32).filter(move |&n| self.is_enabled(context, n))
+    }
+
+    /// Checks that no source has a priority and no source is enabled on any
+    /// context, which is the state `clear_interrupt_enable_all` and
+    /// `clear_interrupt_priotiry_all` should leave behind.
+    pub fn is_cleared(&self) -> bool {
+        self.priorities.iter().all(|&p| p == 0)
+            && self.enables.iter().flatten().all(|&bits| bits == 0)
+    }
+
+    /// Iterates over everything that changed since the `earlier` snapshot
+    pub fn diff<'a>(&'a self, earlier: &'a PlicSnapshot) -> impl Iterator<Item = PlicChange> + 'a {
+        let priorities = (1..NUMBER_INTERRUPTS as u32).filter_map(move |n| {
+            let (old, new) = (earlier.priorities[n as usize], self.priorities[n as usize]);
+            (old != new).then_some(PlicChange::Priority {
+                interrupt: n,
+                old,
+                new,
+            })
+        });
+        let pending = (1..NUMBER_INTERRUPTS as u32).filter_map(move |n| {
+            let pending = self.is_pending(n);
+            (earlier.is_pending(n) != pending).then_some(PlicChange::Pending {
+                interrupt: n,
+                pending,
+            })
+        });
+        let enables = (0..NUMBER_CONTEXTS).map(Context).flat_map(move |context| {
+            (1..NUMBER_INTERRUPTS as u32).filter_map(move |n| {
+                let enabled = self.is_enabled(context, n);
+                (earlier.is_enabled(context, n) != enabled).then_some(PlicChange::Enable {
+                    context,
+                    interrupt: n,
+                    enabled,
+                })
+            })
+        });
+        let thresholds = (0..NUMBER_CONTEXTS).filter_map(move |i| {
+            let (old, new) = (earlier.thresholds[i], self.thresholds[i]);
+            (old != new).then_some(PlicChange::Threshold {
+                context: Context(i),
+                old,
+                new,
+            })
+        });
+        priorities.chain(pending).chain(enables).chain(thresholds)
+    }
+}
+
+/// Compact listing of the snapshot.  Only sources with a priority, pending
+/// sources and contexts with enabled sources or a threshold are shown.
+impl fmt::Display for PlicSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "priorities:")?;
+        for (n, &priority) in self.priorities.iter().enumerate() {
+            if priority != 0 {
+                write!(f, " {n}={priority}")?;
+            }
+        }
+        write!(f, "\r\npending:")?;
+        for n in (1..NUMBER_INTERRUPTS as u32).filter(|&n| self.is_pending(n)) {
+            write!(f, " {n}")?;
+        }
+        for i in 0..NUMBER_CONTEXTS {
+            let context = Context(i);
+            let threshold = self.thresholds[i];
+            if threshold == 0 && self.enabled(context).next().is_none() {
+                continue;
+            }
+            write!(f, "\r\n{context} threshold {threshold} enabled:")?;
+            for n in self.enabled(context) {
+                write!(f, " {n}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //The table default_isr_this_has_to_be_wrong.rs used to hand-copy the
+    //context registers from
+    const PLIC_HART0_MMODE_ENABLES: usize = PLIC_BASE + 0x2000;
+
+    const PLIC_HART1_MMODE_ENABLES: usize = PLIC_BASE + 0x2080;
+    const PLIC_HART1_SMODE_ENABLES: usize = PLIC_BASE + 0x2100;
+
+    const PLIC_HART2_MMODE_ENABLES: usize = PLIC_BASE + 0x2180;
+    const PLIC_HART2_SMODE_ENABLES: usize = PLIC_BASE + 0x2200;
+
+    const PLIC_HART3_MMODE_ENABLES: usize = PLIC_BASE + 0x2280;
+    const PLIC_HART3_SMODE_ENABLES: usize = PLIC_BASE + 0x2300;
+
+    const PLIC_HART4_MMODE_ENABLES: usize = PLIC_BASE + 0x2380;
+    const PLIC_HART4_SMODE_ENABLES: usize = PLIC_BASE + 0x2400;
+
+    const PILC_HART0_MMODE_PRIORITY_THRESHOLD: usize = PLIC_BASE + 0x20_0000;
+    const PILC_HART0_MMODE_CLAIM_COMPLETE: usize = PLIC_BASE + 0x20_0004;
+
+    const PILC_HART1_MMODE_PRIORITY_THRESHOLD: usize = PLIC_BASE + 0x20_1000;
+    const PILC_HART1_MMODE_CLAIM_COMPLETE: usize = PLIC_BASE + 0x20_1004;
+    const PILC_HART1_SMODE_PRIORITY_THRESHOLD: usize = PLIC_BASE + 0x20_2000;
+    const PILC_HART1_SMODE_CLAIM_COMPLETE: usize = PLIC_BASE + 0x20_2004;
+
+    const PILC_HART2_MMODE_PRIORITY_THRESHOLD: usize = PLIC_BASE + 0x20_3000;
+    const PILC_HART2_MMODE_CLAIM_COMPLETE: usize = PLIC_BASE + 0x20_3004;
+    const PILC_HART2_SMODE_PRIORITY_THRESHOLD: usize = PLIC_BASE + 0x20_4000;
+    const PILC_HART2_SMODE_CLAIM_COMPLETE: usize = PLIC_BASE + 0x20_4004;
+
+    const PILC_HART3_MMODE_PRIORITY_THRESHOLD: usize = PLIC_BASE + 0x20_5000;
+    const PILC_HART3_MMODE_CLAIM_COMPLETE: usize = PLIC_BASE + 0x20_5004;
+    const PILC_HART3_SMODE_PRIORITY_THRESHOLD: usize = PLIC_BASE + 0x20_6000;
+    const PILC_HART3_SMODE_CLAIM_COMPLETE: usize = PLIC_BASE + 0x20_6004;
+
+    const PILC_HART4_MMODE_PRIORITY_THRESHOLD: usize = PLIC_BASE + 0x20_7000;
+    const PILC_HART4_MMODE_CLAIM_COMPLETE: usize = PLIC_BASE + 0x20_7004;
+    const PILC_HART4_SMODE_PRIORITY_THRESHOLD: usize = PLIC_BASE + 0x20_8000;
+    const PILC_HART4_SMODE_CLAIM_COMPLETE: usize = PLIC_BASE + 0x20_8004;
+
+    /// (hart, mode, enables, threshold, claim/complete)
+    const TABLE: [(HartId, ExecutionMode, usize, usize, usize); NUMBER_CONTEXTS] = [
+        (
+            HartId::Hart0,
+            ExecutionMode::Machine,
+            PLIC_HART0_MMODE_ENABLES,
+            PILC_HART0_MMODE_PRIORITY_THRESHOLD,
+            PILC_HART0_MMODE_CLAIM_COMPLETE,
+        ),
+        (
+            HartId::Hart1,
+            ExecutionMode::Machine,
+            PLIC_HART1_MMODE_ENABLES,
+            PILC_HART1_MMODE_PRIORITY_THRESHOLD,
+            PILC_HART1_MMODE_CLAIM_COMPLETE,
+        ),
+        (
+            HartId::Hart1,
+            ExecutionMode::Supervisor,
+            PLIC_HART1_SMODE_ENABLES,
+            PILC_HART1_SMODE_PRIORITY_THRESHOLD,
+            PILC_HART1_SMODE_CLAIM_COMPLETE,
+        ),
+        (
+            HartId::Hart2,
+            ExecutionMode::Machine,
+            PLIC_HART2_MMODE_ENABLES,
+            PILC_HART2_MMODE_PRIORITY_THRESHOLD,
+            PILC_HART2_MMODE_CLAIM_COMPLETE,
+        ),
+        (
+            HartId::Hart2,
+            ExecutionMode::Supervisor,
+            PLIC_HART2_SMODE_ENABLES,
+            PILC_HART2_SMODE_PRIORITY_THRESHOLD,
+            PILC_HART2_SMODE_CLAIM_COMPLETE,
+        ),
+        (
+            HartId::Hart3,
+            ExecutionMode::Machine,
+            PLIC_HART3_MMODE_ENABLES,
+            PILC_HART3_MMODE_PRIORITY_THRESHOLD,
+            PILC_HART3_MMODE_CLAIM_COMPLETE,
+        ),
+        (
+            HartId::Hart3,
+            ExecutionMode::Supervisor,
+            PLIC_HART3_SMODE_ENABLES,
+            PILC_HART3_SMODE_PRIORITY_THRESHOLD,
+            PILC_HART3_SMODE_CLAIM_COMPLETE,
+        ),
+        (
+            HartId::Hart4,
+            ExecutionMode::Machine,
+            PLIC_HART4_MMODE_ENABLES,
+            PILC_HART4_MMODE_PRIORITY_THRESHOLD,
+            PILC_HART4_MMODE_CLAIM_COMPLETE,
+        ),
+        (
+            HartId::Hart4,
+            ExecutionMode::Supervisor,
+            PLIC_HART4_SMODE_ENABLES,
+            PILC_HART4_SMODE_PRIORITY_THRESHOLD,
+            PILC_HART4_SMODE_CLAIM_COMPLETE,
+        ),
+    ];
+
+    #[test]
+    fn context_addresses_match_old_table() {
+        for (hart, mode, enables, threshold, claim_complete) in TABLE {
+            let context = Context::new(hart, mode).unwrap();
+            assert_eq!(context.enable_address(), enables, "{context}");
+            assert_eq!(context.threshold_address(), threshold, "{context}");
+            assert_eq!(
+                context.claim_complete_address(),
+                claim_complete,
+                "{context}"
+            );
+            assert_eq!(context.hart(), hart);
+            assert_eq!(context.mode(), mode);
+        }
+    }
+
+    #[test]
+    fn contexts_cover_table_in_order() {
+        let computed: Vec<_> = contexts().map(|(hart, mode, _)| (hart, mode)).collect();
+        let expected: Vec<_> = TABLE.iter().map(|&(hart, mode, ..)| (hart, mode)).collect();
+        assert_eq!(computed, expected);
+        assert!(Context::new(HartId::Hart0, ExecutionMode::Supervisor).is_none());
+    }
+
+    #[test]
+    fn source_registers() {
+        assert_eq!(priority_address(1), PLIC_BASE + 0x4);
+        assert_eq!(pending_address(33), PLIC_BASE + 0x1004);
+        let context = Context::new(HartId::Hart1, ExecutionMode::Machine).unwrap();
+        assert_eq!(
+            enable_address(context, 136),
+            PLIC_HART1_MMODE_ENABLES + 0x10
+        );
+        assert_eq!(bit_mask(136), 1 << 8);
+    }
+
+    fn cleared() -> PlicSnapshot {
+        PlicSnapshot {
+            priorities: [0; NUMBER_INTERRUPTS],
+            pending: [0; NUMBER_INTERRUPT_REGISTERS],
+            enables: [[0; NUMBER_INTERRUPT_REGISTERS]; NUMBER_CONTEXTS],
+            thresholds: [0; NUMBER_CONTEXTS],
+        }
+    }
+
+    #[test]
+    fn snapshot_diff_and_display() {
+        let hart1 = Context::new(HartId::Hart1, ExecutionMode::Machine).unwrap();
+        let hart2 = Context::new(HartId::Hart2, ExecutionMode::Machine).unwrap();
+        let earlier = cleared();
+        let mut later = cleared();
+        later.priorities[5] = 3;
+        later.pending[0] |= bit_mask(7);
+        later.enables[hart1.index()][0] |= bit_mask(5);
+        later.enables[hart2.index()][1] |= bit_mask(40);
+        later.thresholds[hart1.index()] = 2;
+
+        assert!(earlier.is_cleared());
+        assert!(!later.is_cleared());
+        assert_eq!(later.diff(&later).count(), 0);
+        let changes: Vec<_> = later.diff(&earlier).collect();
+        assert_eq!(
+            changes,
+            [
+                PlicChange::Priority {
+                    interrupt: 5,
+                    old: 0,
+                    new: 3
+                },
+                PlicChange::Pending {
+                    interrupt: 7,
+                    pending: true
+                },
+                PlicChange::Enable {
+                    context: hart1,
+                    interrupt: 5,
+                    enabled: true
+                },
+                PlicChange::Enable {
+                    context: hart2,
+                    interrupt: 40,
+                    enabled: true
+                },
+                PlicChange::Threshold {
+                    context: hart1,
+                    old: 0,
+                    new: 2
+                },
+            ]
+        );
+        let printed: Vec<_> = changes.iter().map(|c| c.to_string()).collect();
+        assert_eq!(
+            printed,
+            [
+                "priority 5: 0 -> 3",
+                "pending 7: true",
+                "enable Hart1/Machine 5: true",
+                "enable Hart2/Machine 40: true",
+                "threshold Hart1/Machine: 0 -> 2",
+            ]
+        );
+        //Going back reports the same changes the other way round
+        let reverted: Vec<_> = earlier.diff(&later).collect();
+        assert_eq!(
+            reverted[2],
+            PlicChange::Enable {
+                context: hart1,
+                interrupt: 5,
+                enabled: false
+            }
+        );
+
+        assert_eq!(
+            later.to_string(),
+            "priorities: 5=3\r\npending: 7\r\n\
+             Hart1/Machine threshold 2 enabled: 5\r\n\
+             Hart2/Machine threshold 0 enabled: 40"
+        );
+        assert_eq!(earlier.to_string(), "priorities:\r\npending:");
+    }
+}
@@ -0,0 +1,212 @@
+//! Software timers multiplexed on one SI5 timer channel.
+//!
+//! Up to [MAX_TIMERS] one-shot and periodic virtual timers share `TIMER1`.
+//! Deadlines are kept as [Instant]s and the channel runs in single shot mode,
+//! loaded with the time left until the nearest deadline instead of ticking at a
+//! fixed rate.  Timers can be added and cancelled from main or from
+//! interrupt handlers.  Callbacks run from the `TIMER1` interrupt handler with
+//! the timer list unlocked, so they can add and cancel timers themselves.
+
+use heapless::Vec;
+use jh7110_pac::Interrupt;
+
+use crate::{
+    default_isr_this_has_to_be_wrong::{enable_interrupt, register_handler_fn, InterruptPriority},
+    mono::{self, Duration, Instant},
+    println,
+    timer::{Hertz, Timer, Timer1, TimerControl, TimerEnable, TimerIntClearStatus, TimerIntMask},
+};
+
+/// Maximum number of timers running at the same time
+pub const MAX_TIMERS: usize = 16;
+
+/// Handle of a timer returned when adding it, used to cancel it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimerId {
+    slot: u8,
+    /// Tells a timer apart from a later one reusing its slot
+    generation: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SoftTimerError {
+    /// [init] wasn't called
+    NotInitialized,
+    /// All [MAX_TIMERS] slots are in use
+    Full,
+    /// A periodic timer needs a period of at least one mtime tick
+    ZeroPeriod,
+}
+
+#[derive(Clone, Copy)]
+struct Entry {
+    deadline: Instant,
+    /// Time between expiries of a periodic timer, None for a one-shot timer
+    period: Option<Duration>,
+    callback: fn(),
+    generation: u16,
+}
+
+struct Wheel {
+    channel: Option<Timer1>,
+    entries: [Option<Entry>; MAX_TIMERS],
+    /// Generation handed to the next timer added
+    generation: u16,
+}
+
+//The channel is only touched with the lock held
+unsafe impl Send for Wheel {}
+
+static WHEEL: spin::Mutex<Wheel> = spin::Mutex::new(Wheel {
+    channel: None,
+    entries: [None; MAX_TIMERS],
+    generation: 0,
+});
+
+impl Wheel {
+    fn add(
+        &mut self,
+        deadline: Instant,
+        period: Option<Duration>,
+        callback: fn(),
+    ) -> Result<TimerId, SoftTimerError> {
+        if self.channel.is_none() {
+            return Err(SoftTimerError::NotInitialized);
+        }
+        let slot = self
+            .entries
+            .iter()
+            .position(Option::is_none)
+            .ok_or(SoftTimerError::Full)?;
+        let generation = self.generation;
+        self.generation = self.generation.wrapping_add(1);
+        self.entries[slot] = Some(Entry {
+            deadline,
+            period,
+            callback,
+            generation,
+        });
+        self.reprogram();
+        Ok(TimerId {
+            slot: slot as u8,
+            generation,
+        })
+    }
+
+    fn cancel(&mut self, id: TimerId) -> bool {
+        let entry = &mut self.entries[id.slot as usize];
+        match entry {
+            Some(e) if e.generation == id.generation => {
+                *entry = None;
+                self.reprogram();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Loads the channel with the time left until the nearest deadline, or stops
+    /// it if no timer is running
+    fn reprogram(&self) {
+        let Some(channel) = &self.channel else {
+            return;
+        };
+        channel.set_enable(TimerEnable::Disable);
+        let Some(deadline) = self.entries.iter().flatten().map(|e| e.deadline).min() else {
+            return;
+        };
+        let Some(Hertz(hz)) = channel.clock() else {
+            return;
+        };
+        let left = deadline.ticks().saturating_sub(mono::ticks());
+        let ticks = left as u128 * hz as u128 / mono::MTIME_HZ as u128;
+        //A deadline further out than the counter reaches is handled by
+        //expiring early and reprogramming for the rest
+        channel.set_load(ticks.clamp(1, u32::MAX as u128) as u32);
+        channel.reload_counter();
+        channel.set_enable(TimerEnable::Enable);
+    }
+
+    /// Removes the expired one-shot timers, moves the expired periodic ones to
+    /// their next deadline and collects the callbacks of both
+    fn expire(&mut self, now: Instant) -> Vec<fn(), MAX_TIMERS> {
+        let mut callbacks = Vec::new();
+        for entry in self.entries.iter_mut() {
+            let Some(e) = entry else {
+                continue;
+            };
+            if e.deadline > now {
+                continue;
+            }
+            //Every slot holds at most one callback, it always fits
+            let _ = callbacks.push(e.callback);
+            match e.period {
+                Some(period) => {
+                    e.deadline += period;
+                    //Skip the expiries that were missed instead of firing
+                    //them back to back
+                    if e.deadline <= now {
+                        e.deadline = now + period;
+                    }
+                }
+                None => *entry = None,
+            }
+        }
+        callbacks
+    }
+}
+
+/// Takes over the channel and starts the timer service
+pub fn init(channel: Timer1) {
+    channel.set_int_mask(TimerIntMask::Mask);
+    channel.set_enable(TimerEnable::Disable);
+    channel.set_control(TimerControl::Single);
+    channel.set_int_status_clear(TimerIntClearStatus::Clear);
+    channel.set_int_mask(TimerIntMask::Unmask);
+    riscv::interrupt::free(|| {
+        WHEEL.lock().channel = Some(channel);
+    });
+    if register_handler_fn(Interrupt::TIMER1, soft_timer_isr).is_err() {
+        println!("TIMER1 handler already registered");
+    }
+    enable_interrupt(Interrupt::TIMER1, InterruptPriority::Priority5);
+}
+
+/// Runs `callback` once at `deadline`
+pub fn add_at(deadline: Instant, callback: fn()) -> Result<TimerId, SoftTimerError> {
+    riscv::interrupt::free(|| WHEEL.lock().add(deadline, None, callback))
+}
+
+/// Runs `callback` once after `delay`
+pub fn add_oneshot(delay: Duration, callback: fn()) -> Result<TimerId, SoftTimerError> {
+    add_at(Instant::now() + delay, callback)
+}
+
+/// Runs `callback` every `period`, starting one period from now
+pub fn add_periodic(period: Duration, callback: fn()) -> Result<TimerId, SoftTimerError> {
+    if mono::duration_to_ticks(period) == 0 {
+        return Err(SoftTimerError::ZeroPeriod);
+    }
+    let deadline = Instant::now() + period;
+    riscv::interrupt::free(|| WHEEL.lock().add(deadline, Some(period), callback))
+}
+
+/// Stops the timer.  Returns false if it already expired or was cancelled.
+pub fn cancel(id: TimerId) -> bool {
+    riscv::interrupt::free(|| WHEEL.lock().cancel(id))
+}
+
+fn soft_timer_isr() {
+    let callbacks = riscv::interrupt::free(|| {
+        let mut wheel = WHEEL.lock();
+        if let Some(channel) = &wheel.channel {
+            channel.set_int_status_clear(TimerIntClearStatus::Clear);
+        }
+        let callbacks = wheel.expire(Instant::now());
+        wheel.reprogram();
+        callbacks
+    });
+    for callback in callbacks {
+        callback();
+    }
+}
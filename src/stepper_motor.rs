@@ -19,7 +19,7 @@ use jh7110_hal::{
 use jh7110_pac::Interrupt;
 
 use crate::{
-    default_isr_this_has_to_be_wrong::{enable_interrupt, InterruptPriority},
+    default_isr_this_has_to_be_wrong::{enable_interrupt, register_handler, InterruptPriority},
     println,
 };
 
@@ -131,16 +131,26 @@ fn init_gpio<
             .clear_bit() //disable active pull down capability
     });
 
-    unsafe {
-        STEP_COUNTER = 0;
-        MOVE_COMMAND.num_steps = 100;
-        MOVE_COMMAND.direction = MotorDirection::Forward;
-        let d = p.sys_pinctrl.padcfg().gpio39();
-        let d = gpio::get_gpio(d);
-        let mut d = d.into_enabled_output();
-        d.set_pin(bool::from(MOVE_COMMAND.direction));
+    //The stepper state only lives here and is handed to the interrupt handler
+    static mut STEPPER: Stepper = Stepper {
+        move_command: StepMove {
+            num_steps: 100,
+            direction: MotorDirection::Forward,
+        },
+        step_counter: 0,
+    };
+    let stepper = unsafe { &mut *core::ptr::addr_of_mut!(STEPPER) };
+    stepper.step_counter = 0;
+    stepper.move_command.num_steps = 100;
+    stepper.move_command.direction = MotorDirection::Forward;
+    let d = p.sys_pinctrl.padcfg().gpio39();
+    let d = gpio::get_gpio(d);
+    let mut d = d.into_enabled_output();
+    d.set_pin(bool::from(stepper.move_command.direction));
+
+    if register_handler(Interrupt::PTC1, step_pwm_interrupt_handler, stepper).is_err() {
+        println!("PTC1 handler already registered");
     }
-
     enable_interrupt(Interrupt::PTC1, InterruptPriority::Priority7)
 
     //
@@ -166,16 +176,13 @@ struct StepMove {
     direction: MotorDirection,
 }
 
-static mut MOVE_COMMAND: StepMove = StepMove {
-    num_steps: 100,
-    direction: MotorDirection::Forward,
-};
-
-static mut STEP_COUNTER: usize = 0;
+/// State of the stepper handed to [step_pwm_interrupt_handler]
+struct Stepper {
+    move_command: StepMove,
+    step_counter: usize,
+}
 
-pac::interrupt!(PTC1, step_pwm_interrupt_handler);
-#[no_mangle]
-fn step_pwm_interrupt_handler() {
+fn step_pwm_interrupt_handler(stepper: &mut Stepper) {
     let p = unsafe { pac::Pwm1::steal() };
     //Print clock values to see interrupt
     let cnt = p.cntr().read().bits();
@@ -199,41 +206,40 @@ fn step_pwm_interrupt_handler() {
     //HRC match -> cnt: 12000058, hrc: 12000000, lrc: 24000000, ctrl:       0x79
     //LRC match -> cnt: 24000000, hrc: 12000000, lrc: 24000000, ctrl:       0x79
     if cnt < hrc || cnt == lrc {
-        unsafe {
-            STEP_COUNTER += 1;
-            match MOVE_COMMAND.num_steps {
-                0 => {
-                    MOVE_COMMAND.num_steps = 100;
-                    MOVE_COMMAND.direction = match MOVE_COMMAND.direction {
-                        MotorDirection::Forward => MotorDirection::Retrograde,
-                        MotorDirection::Retrograde => MotorDirection::Forward,
-                    };
-                    //Clear the counter and put it in reset.  Clearing counter is
-                    //probasbly not needed.
-                    p.cntr().modify(|_, w| w.cntr().variant(0));
-                    p.ctrl().modify(|_, w| w.cntrrst().set_bit());
-                    println!("End of move. {}", STEP_COUNTER);
-                    //Setup to revirse direction
-                    let d = pac::SysPinctrl::steal();
-                    let d = d.padcfg().gpio39();
-                    let d = gpio::get_gpio(d);
-                    let mut d = d.into_enabled_output();
-                    d.set_pin(bool::from(MOVE_COMMAND.direction));
-                    println!("Direction: {:?}", MOVE_COMMAND.direction);
-                    p.ctrl()
-                        .modify(|_, w| w.single().clear_bit().cntrrst().clear_bit())
-                }
-                1 => {
-                    //This is the last period of the move command so enable one shot
-                    p.ctrl().modify(|_, w| w.single().set_bit());
-                    MOVE_COMMAND.num_steps -= 1;
-                    println!("Last Step {}", STEP_COUNTER);
-                }
-                _ => {
-                    //Decrement the number of steps
-                    MOVE_COMMAND.num_steps -= 1;
-                    println!("Stepping {}", STEP_COUNTER);
-                }
+        stepper.step_counter += 1;
+        let move_command = &mut stepper.move_command;
+        match move_command.num_steps {
+            0 => {
+                move_command.num_steps = 100;
+                move_command.direction = match move_command.direction {
+                    MotorDirection::Forward => MotorDirection::Retrograde,
+                    MotorDirection::Retrograde => MotorDirection::Forward,
+                };
+                //Clear the counter and put it in reset.  Clearing counter is
+                //probasbly not needed.
+                p.cntr().modify(|_, w| w.cntr().variant(0));
+                p.ctrl().modify(|_, w| w.cntrrst().set_bit());
+                println!("End of move. {}", stepper.step_counter);
+                //Setup to revirse direction
+                let d = unsafe { pac::SysPinctrl::steal() };
+                let d = d.padcfg().gpio39();
+                let d = gpio::get_gpio(d);
+                let mut d = d.into_enabled_output();
+                d.set_pin(bool::from(move_command.direction));
+                println!("Direction: {:?}", move_command.direction);
+                p.ctrl()
+                    .modify(|_, w| w.single().clear_bit().cntrrst().clear_bit())
+            }
+            1 => {
+                //This is the last period of the move command so enable one shot
+                p.ctrl().modify(|_, w| w.single().set_bit());
+                move_command.num_steps -= 1;
+                println!("Last Step {}", stepper.step_counter);
+            }
+            _ => {
+                //Decrement the number of steps
+                move_command.num_steps -= 1;
+                println!("Stepping {}", stepper.step_counter);
             }
         }
     }
@@ -1,7 +1,26 @@
 #[allow(unused)]
-use core::{marker::PhantomData, ptr};
-
-use crate::println;
+use core::{
+    marker::PhantomData,
+    ptr,
+    sync::atomic::{AtomicBool, AtomicU8, Ordering},
+    time::Duration,
+};
+
+use embedded_hal::delay::DelayNs;
+use embedded_hal_0_2::timer::CountDown;
+use jh7110_pac as pac;
+use riscv::register::{mie, mstatus};
+use void::Void;
+
+use crate::{
+    default_isr_this_has_to_be_wrong::{
+        disable_interrupt_on, enable_interrupt_on, register_handler_fn, unregister_handler,
+        InterruptPriority,
+    },
+    mono::NANOS_PER_SECOND,
+    plic::{ExecutionMode, HartId, HartMask},
+    println,
+};
 
 #[repr(u8)]
 #[derive(Debug)]
@@ -104,11 +123,34 @@ impl TimerIntMask {
     }
 }
 
+/// Rate of the timer channel clocks.  On the JH7110 the SYSCRG
+/// `clk_tim01_0`..`clk_tim01_3` clocks are gate-only children of the 24MHz
+/// `clk_osc`, there is no mux or divider to read back, so the rate is fixed
+/// whenever the gate is open.
+pub const TIMER_CLK_HZ: u32 = 24_000_000;
+
+/// Frequency in Hertz
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Hertz(pub u32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimerError {
+    /// The channel clock is gated, bring the channel up with take() first
+    ClockGated,
+    /// The period is shorter than one tick of the channel clock
+    PeriodTooShort,
+    /// The period needs more ticks than the 32 bit counter holds
+    PeriodTooLong,
+}
+
 pub trait Timer {
     /// Timer Bias (basically an offset), must be implimented by struct
     /// implimentation
     const CHANNEL: u32;
 
+    /// Frequency of the clock the channel counts, None if the clock is gated
+    fn clock(&self) -> Option<Hertz>;
+
     /// Define timer register offsets
     const INT_STATUS_REG_OFFSET: u32 = 0x00;
     const CONTROL_REG_OFFSET: u32 = 0x04;
@@ -216,6 +258,40 @@ pub trait Timer {
         unsafe { ptr::write_volatile(Self::INT_MASK_REG as *mut u32, mask as u32) }
     }
 
+    /// Sets the load value so the counter expires every `period`
+    fn set_period(&self, period: Duration) -> Result<(), TimerError> {
+        let Hertz(hz) = self.clock().ok_or(TimerError::ClockGated)?;
+        let ticks = period.as_nanos() * hz as u128 / NANOS_PER_SECOND as u128;
+        match ticks {
+            0 => Err(TimerError::PeriodTooShort),
+            ticks if ticks > u32::MAX as u128 => Err(TimerError::PeriodTooLong),
+            ticks => {
+                self.set_load(ticks as u32);
+                Ok(())
+            }
+        }
+    }
+
+    /// Sets the load value so the counter expires `frequency` times a second
+    fn set_frequency(&self, frequency: Hertz) -> Result<(), TimerError> {
+        let Hertz(hz) = self.clock().ok_or(TimerError::ClockGated)?;
+        match frequency.0 {
+            0 => Err(TimerError::PeriodTooLong),
+            f if f > hz => Err(TimerError::PeriodTooShort),
+            f => {
+                self.set_load(hz / f);
+                Ok(())
+            }
+        }
+    }
+
+    /// Time left until the counter expires
+    fn remaining(&self) -> Result<Duration, TimerError> {
+        let Hertz(hz) = self.clock().ok_or(TimerError::ClockGated)?;
+        let ticks = self.get_counter() as u64;
+        Ok(Duration::from_nanos(ticks * NANOS_PER_SECOND / hz as u64))
+    }
+
     /// Prints timer information
     fn print_debug_info(&self) {
         println!("TIMER_REG_BASE:       {:#10x}", Self::TIMER_REG_BASE);
@@ -266,58 +342,276 @@ pub trait Timer {
     }
 }
 
-/// Struct to access Timer0
-pub struct Timer0 {
+/// Number of SI5 timer channels
+pub const NUMBER_CHANNELS: u32 = 4;
+
+/// Bit per channel that is currently handed out by [TimerChannel::take]
+static TAKEN: AtomicU8 = AtomicU8::new(0);
+
+/// Struct to access timer channel `N`.  Only one instance per channel is handed
+/// out by [TimerChannel::take] until it's given back with [TimerChannel::free].
+pub struct TimerChannel<const N: u32> {
     /// I dont know why I should put this here
     _marker: PhantomData<*const ()>,
 }
 
-impl Timer0 {
-    pub fn new() -> Self {
+pub type Timer0 = TimerChannel<0>;
+pub type Timer1 = TimerChannel<1>;
+pub type Timer2 = TimerChannel<2>;
+pub type Timer3 = TimerChannel<3>;
+
+impl<const N: u32> TimerChannel<N> {
+    /// Fails to compile for a channel the SI5 timer doesn't have
+    const VALID: () = assert!(N < NUMBER_CHANNELS, "the SI5 timer has 4 channels");
+    const TAKEN_BIT: u8 = 1 << N;
+
+    /// Takes the channel, bringing up its clocks and resets.  Returns None if
+    /// the channel was already taken and not freed.
+    pub fn take() -> Option<Self> {
+        let () = Self::VALID;
+        if TAKEN.fetch_or(Self::TAKEN_BIT, Ordering::Acquire) & Self::TAKEN_BIT != 0 {
+            return None;
+        }
+        Self::bring_up();
+        Some(Self {
+            _marker: PhantomData,
+        })
+    }
+
+    /// Gets the channel regardless of whether it was taken.
+    ///
+    /// # Safety
+    /// The caller has to make sure it doesn't fight with the owner of the
+    /// channel, e.g. by only clearing the interrupt status from its handler.
+    /// The channel has to have been brought up by [TimerChannel::take] before.
+    pub unsafe fn steal() -> Self {
+        let () = Self::VALID;
         Self {
             _marker: PhantomData,
         }
     }
-}
 
-impl Timer for Timer0 {
-    const CHANNEL: u32 = 0;
-}
+    /// Stops the channel, gates its clock and hands it back so it can be taken
+    /// again
+    pub fn free(self) {
+        self.set_int_mask(TimerIntMask::Mask);
+        self.set_enable(TimerEnable::Disable);
+        let sys_crg = unsafe { &*pac::Syscrg::ptr() };
+        match N {
+            0 => sys_crg
+                .clk_tim()
+                .tim01_0()
+                .modify(|_, w| w.clk_icg().clear_bit()),
+            1 => sys_crg
+                .clk_tim()
+                .tim01_1()
+                .modify(|_, w| w.clk_icg().clear_bit()),
+            2 => sys_crg
+                .clk_tim()
+                .tim01_2()
+                .modify(|_, w| w.clk_icg().clear_bit()),
+            _ => sys_crg
+                .clk_tim()
+                .tim01_3()
+                .modify(|_, w| w.clk_icg().clear_bit()),
+        };
+        TAKEN.fetch_and(!Self::TAKEN_BIT, Ordering::Release);
+    }
 
-/// Struct to access Timer1
-pub struct Timer1 {
-    /// I dont know why I should put this here
-    _marker: PhantomData<*const ()>,
+    /// Enables the shared APB clock and the channel clock and takes both out of
+    /// reset
+    fn bring_up() {
+        let sys_crg = unsafe { &*pac::Syscrg::ptr() };
+        //Enable the timer Advanced Preriphial BUS clock, shared by all channels
+        sys_crg.clk_tim().apb().modify(|_, w| w.clk_icg().set_bit());
+        //Enable the channel clock
+        match N {
+            0 => sys_crg
+                .clk_tim()
+                .tim01_0()
+                .modify(|_, w| w.clk_icg().set_bit()),
+            1 => sys_crg
+                .clk_tim()
+                .tim01_1()
+                .modify(|_, w| w.clk_icg().set_bit()),
+            2 => sys_crg
+                .clk_tim()
+                .tim01_2()
+                .modify(|_, w| w.clk_icg().set_bit()),
+            _ => sys_crg
+                .clk_tim()
+                .tim01_3()
+                .modify(|_, w| w.clk_icg().set_bit()),
+        };
+        //Clear the timer apb reset bit and the channel reset bit
+        let rst3 = sys_crg.rst().software_address_selector().rst3();
+        rst3.modify(|_, w| w.u0_si5_timer_apb().clear_bit());
+        match N {
+            0 => rst3.modify(|_, w| w.u0_si5_timer_0().clear_bit()),
+            1 => rst3.modify(|_, w| w.u0_si5_timer_1().clear_bit()),
+            2 => rst3.modify(|_, w| w.u0_si5_timer_2().clear_bit()),
+            _ => rst3.modify(|_, w| w.u0_si5_timer_3().clear_bit()),
+        };
+    }
 }
 
-impl Timer1 {
-    pub fn new() -> Self {
-        Self {
-            _marker: PhantomData,
+impl<const N: u32> Timer for TimerChannel<N> {
+    const CHANNEL: u32 = N;
+
+    /// [TIMER_CLK_HZ] while the channel's clock gate is open
+    fn clock(&self) -> Option<Hertz> {
+        let sys_crg = unsafe { &*pac::Syscrg::ptr() };
+        let enabled = match N {
+            0 => sys_crg.clk_tim().tim01_0().read().clk_icg().bit_is_set(),
+            1 => sys_crg.clk_tim().tim01_1().read().clk_icg().bit_is_set(),
+            2 => sys_crg.clk_tim().tim01_2().read().clk_icg().bit_is_set(),
+            _ => sys_crg.clk_tim().tim01_3().read().clk_icg().bit_is_set(),
+        };
+        match enabled {
+            true => Some(Hertz(TIMER_CLK_HZ)),
+            false => None,
         }
     }
 }
 
-impl Timer for Timer1 {
-    const CHANNEL: u32 = 1;
+/// Waits shorter than this are busy-polled, longer ones sleep in `wfi` until
+/// the channel interrupt fires
+const SLEEP_THRESHOLD: Duration = Duration::from_micros(100);
+
+const NOT_FIRED: AtomicBool = AtomicBool::new(false);
+/// Set by [delay_isr] when the channel a delay sleeps on expired
+static FIRED: [AtomicBool; NUMBER_CHANNELS as usize] = [NOT_FIRED; NUMBER_CHANNELS as usize];
+
+/// Clears the channel interrupt and wakes the delay sleeping on it
+fn delay_isr<const N: u32>() {
+    let channel = unsafe { TimerChannel::<N>::steal() };
+    channel.set_int_status_clear(TimerIntClearStatus::Clear);
+    FIRED[N as usize].store(true, Ordering::Release);
 }
 
-/// Struct to access Timer2
-pub struct Timer2 {
-    /// I dont know why I should put this here
-    _marker: PhantomData<*const ()>,
+impl<const N: u32> TimerChannel<N> {
+    /// PLIC source of the channel
+    pub const INTERRUPT: pac::Interrupt = match N {
+        0 => pac::Interrupt::TIMER0,
+        1 => pac::Interrupt::TIMER1,
+        2 => pac::Interrupt::TIMER2,
+        _ => pac::Interrupt::TIMER3,
+    };
+
+    /// Starts the channel counting `period` once with its interrupt masked
+    fn start_single(&self, period: Duration) -> Result<(), TimerError> {
+        self.set_int_mask(TimerIntMask::Mask);
+        self.set_enable(TimerEnable::Disable);
+        self.set_control(TimerControl::Single);
+        self.set_int_status_clear(TimerIntClearStatus::Clear);
+        self.set_period(period)?;
+        self.reload_counter();
+        self.set_enable(TimerEnable::Enable);
+        Ok(())
+    }
+
+    fn is_expired(&self) -> bool {
+        matches!(self.get_int_status(), TimerIntStatus::Pending)
+    }
+
+    fn busy_wait(&self) {
+        while !self.is_expired() {
+            core::hint::spin_loop();
+        }
+        self.set_int_status_clear(TimerIntClearStatus::Clear);
+    }
+
+    /// Sleeps in `wfi` until the channel interrupt fires.  Falls back to
+    /// busy-polling if interrupts are off on this hart or another handler is
+    /// registered for the channel.
+    fn sleep_wait(&self) {
+        let interrupts_on = mstatus::read().mie() && mie::read().mext();
+        if !interrupts_on || register_handler_fn(Self::INTERRUPT, delay_isr::<N>).is_err() {
+            return self.busy_wait();
+        }
+        let hart = HartMask::from(HartId::current());
+        FIRED[N as usize].store(false, Ordering::Release);
+        if enable_interrupt_on(
+            Self::INTERRUPT,
+            InterruptPriority::Priority1,
+            hart,
+            ExecutionMode::Machine,
+        )
+        .is_err()
+        {
+            unregister_handler(Self::INTERRUPT);
+            return self.busy_wait();
+        }
+        self.set_int_mask(TimerIntMask::Unmask);
+        while !FIRED[N as usize].load(Ordering::Acquire) {
+            riscv::asm::wfi();
+        }
+        self.set_int_mask(TimerIntMask::Mask);
+        disable_interrupt_on(Self::INTERRUPT, hart, ExecutionMode::Machine);
+        unregister_handler(Self::INTERRUPT);
+    }
 }
 
-impl Timer for Timer2 {
-    const CHANNEL: u32 = 2;
+impl<const N: u32> DelayNs for TimerChannel<N> {
+    fn delay_ns(&mut self, ns: u32) {
+        let period = Duration::from_nanos(ns as u64);
+        //Anything shorter than a tick has already passed
+        if self.start_single(period).is_err() {
+            return;
+        }
+        match period < SLEEP_THRESHOLD {
+            true => self.busy_wait(),
+            false => self.sleep_wait(),
+        }
+        self.set_enable(TimerEnable::Disable);
+    }
 }
 
-/// Struct to access Timer3
-pub struct Timer3 {
-    /// I dont know why I should put this here
-    _marker: PhantomData<*const ()>,
+/// Shortest period a channel counts, one tick of [TIMER_CLK_HZ]
+pub const MIN_PERIOD: Duration =
+    Duration::from_nanos(NANOS_PER_SECOND.div_ceil(TIMER_CLK_HZ as u64));
+/// Longest period a channel counts, `u32::MAX` ticks of [TIMER_CLK_HZ] or
+/// about 179 seconds
+pub const MAX_PERIOD: Duration =
+    Duration::from_nanos(u32::MAX as u64 * NANOS_PER_SECOND / TIMER_CLK_HZ as u64);
+
+impl<const N: u32> TimerChannel<N> {
+    /// Starts counting down `period` like [CountDown::start], but returns the
+    /// error instead of clamping a period the channel can't count
+    pub fn try_start(&mut self, period: Duration) -> Result<(), TimerError> {
+        self.start_single(period)
+    }
 }
 
-impl Timer for Timer3 {
-    const CHANNEL: u32 = 3;
+/// Non-blocking countdown.  `embedded-hal` 1.0 and `embedded-hal-nb` have no
+/// timer traits, so this implements the 0.2 one.
+impl<const N: u32> CountDown for TimerChannel<N> {
+    type Time = Duration;
+
+    /// Starts counting down `count`, restarting a countdown still running.
+    /// The trait can't return an error, so the period is clamped to
+    /// [MIN_PERIOD]..=[MAX_PERIOD] and a gated channel clock is opened again.
+    fn start<T>(&mut self, count: T)
+    where
+        T: Into<Duration>,
+    {
+        let period = count.into().clamp(MIN_PERIOD, MAX_PERIOD);
+        if let Err(TimerError::ClockGated) = self.try_start(period) {
+            Self::bring_up();
+            //Can't fail anymore, the period is in range and the clock runs
+            self.try_start(period).ok();
+        }
+    }
+
+    /// Returns `WouldBlock` until the countdown expired, then Ok once
+    fn wait(&mut self) -> nb::Result<(), Void> {
+        match self.is_expired() {
+            true => {
+                self.set_int_status_clear(TimerIntClearStatus::Clear);
+                self.set_enable(TimerEnable::Disable);
+                Ok(())
+            }
+            false => Err(nb::Error::WouldBlock),
+        }
+    }
 }
@@ -0,0 +1,241 @@
+//! Driver for the JH7110 watchdog.
+//!
+//! The counter counts down from the load value.  The first time it reaches zero
+//! it raises the `WDOG` interrupt and reloads, if the interrupt still isn't
+//! cleared the next time it reaches zero the SoC is reset.  [enable] splits the
+//! timeout in these two halves, [feed] clears the interrupt and reloads the
+//! counter.
+//!
+//! The JH7110 has no register telling why it was reset.  The `WDOG` interrupt
+//! writes a crash record instead, so the next boot can tell a watchdog reset
+//! apart from the others.  The interrupt goes to an idle secondary hart if one
+//! is up, so hart 1 wedged in an ISR or with interrupts off still leaves a
+//! record.  With hart 1 running alone it resets without leaving one.
+use core::{
+    ptr,
+    sync::atomic::{AtomicBool, AtomicU8, Ordering},
+    time::Duration,
+};
+
+use jh7110_pac::{self as pac, Interrupt};
+
+use crate::{
+    crash,
+    default_isr_this_has_to_be_wrong::{
+        disable_interrupt_on, enable_interrupt_on, ready_harts, register_handler_fn,
+        InterruptPriority,
+    },
+    mono::NANOS_PER_SECOND,
+    plic::{ExecutionMode, HartMask},
+    println,
+};
+
+/// System memory map start address of the watchdog registers
+const WDT_REG_BASE: usize = 0x1307_0000;
+/// Value the counter is loaded with
+const LOAD_REG: usize = WDT_REG_BASE + 0x000;
+/// Current value of the counter
+const VALUE_REG: usize = WDT_REG_BASE + 0x004;
+/// Bit 0 enables the reset, bit 1 enables the counter and its interrupt
+const CONTROL_REG: usize = WDT_REG_BASE + 0x008;
+const CONTROL_RESET_ENABLE: u32 = 1 << 0;
+const CONTROL_ENABLE: u32 = 1 << 1;
+/// Any write clears the interrupt and reloads the counter
+const INTCLR_REG: usize = WDT_REG_BASE + 0x00C;
+/// Bit 0 is the raw interrupt status
+const RIS_REG: usize = WDT_REG_BASE + 0x010;
+/// Every other register is write protected until the key is written here, any
+/// other value locks them again
+const LOCK_REG: usize = WDT_REG_BASE + 0xC00;
+const UNLOCK_KEY: u32 = 0x1ACC_E551;
+const LOCK_KEY: u32 = 0;
+
+/// Frequency of the watchdog core clock, the 24MHz oscillator
+const CLK_CORE_HZ: u64 = 24_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchdogError {
+    /// The timeout is shorter than two ticks of the watchdog clock
+    TimeoutTooShort,
+    /// Half the timeout needs more ticks than the 32 bit counter holds
+    TimeoutTooLong,
+}
+
+/// Set by [lock], the registers are locked again after every access
+static LOCKED: AtomicBool = AtomicBool::new(false);
+/// Set once the `WDOG` handler is registered
+static HANDLER_REGISTERED: AtomicBool = AtomicBool::new(false);
+/// Set while the `WDOG` source is disabled because the interrupt expired
+/// without being fed
+static EXPIRED: AtomicBool = AtomicBool::new(false);
+/// Hart the `WDOG` interrupt is routed to, as [HartMask] bits
+static WATCHER: AtomicU8 = AtomicU8::new(HartMask::HART1.bits());
+/// Called from the `WDOG` interrupt half way through the timeout
+static PRE_TIMEOUT_CALLBACK: spin::Mutex<Option<fn()>> = spin::Mutex::new(None);
+
+/// Enables the watchdog clocks and takes it out of reset
+fn bring_up() {
+    let sys_crg = unsafe { &*pac::Syscrg::ptr() };
+    //Enable the watchdog Advanced Preriphial BUS clock and core clock
+    sys_crg.clk_wdt().apb().modify(|_, w| w.clk_icg().set_bit());
+    sys_crg
+        .clk_wdt()
+        .core()
+        .modify(|_, w| w.clk_icg().set_bit());
+    //Clear the watchdog apb and core reset bits
+    sys_crg
+        .rst()
+        .software_address_selector()
+        .rst3()
+        .modify(|_, w| {
+            w.u0_dskit_wdt_apb()
+                .clear_bit()
+                .u0_dskit_wdt_core()
+                .clear_bit()
+        });
+}
+
+/// Picks the hart the `WDOG` interrupt is routed to, a secondary hart with a
+/// set up context if there is one, otherwise hart 1
+fn choose_watcher() -> HartMask {
+    let watcher = match ready_harts().difference(HartMask::HART1).harts().next() {
+        Some(hart) => HartMask::from(hart),
+        None => HartMask::HART1,
+    };
+    WATCHER.store(watcher.bits(), Ordering::Relaxed);
+    watcher
+}
+
+/// Hart the `WDOG` interrupt is routed to
+fn watcher() -> HartMask {
+    HartMask::from_bits_truncate(WATCHER.load(Ordering::Relaxed))
+}
+
+/// Runs `f` with the registers unlocked, locking them again afterwards if
+/// [lock] was called
+fn unlocked<R>(f: impl FnOnce() -> R) -> R {
+    unsafe { ptr::write_volatile(LOCK_REG as *mut u32, UNLOCK_KEY) };
+    let result = f();
+    if LOCKED.load(Ordering::Relaxed) {
+        unsafe { ptr::write_volatile(LOCK_REG as *mut u32, LOCK_KEY) };
+    }
+    result
+}
+
+/// Starts the watchdog.  The SoC is reset if it isn't fed for `timeout`, the
+/// pre-timeout callback runs after half of it.
+pub fn enable(timeout: Duration) -> Result<(), WatchdogError> {
+    let ticks = timeout.as_nanos() * CLK_CORE_HZ as u128 / NANOS_PER_SECOND as u128 / 2;
+    let load = match ticks {
+        0 => return Err(WatchdogError::TimeoutTooShort),
+        ticks if ticks > u32::MAX as u128 => return Err(WatchdogError::TimeoutTooLong),
+        ticks => ticks as u32,
+    };
+    bring_up();
+    if !HANDLER_REGISTERED.swap(true, Ordering::Relaxed)
+        && register_handler_fn(Interrupt::WDOG, watchdog_isr).is_err()
+    {
+        println!("WDOG handler already registered");
+    }
+    disable_interrupt_on(Interrupt::WDOG, watcher(), ExecutionMode::Machine);
+    let watcher = choose_watcher();
+    if watcher == HartMask::HART1 {
+        println!("WDOG only on hart 1, a wedged hart 1 resets without a crash record");
+    }
+    if let Err(skipped) = enable_interrupt_on(
+        Interrupt::WDOG,
+        InterruptPriority::Priority7,
+        watcher,
+        ExecutionMode::Machine,
+    ) {
+        println!("WDOG not routed to {:?}", skipped);
+    }
+    unlocked(|| unsafe {
+        ptr::write_volatile(LOAD_REG as *mut u32, load);
+        ptr::write_volatile(INTCLR_REG as *mut u32, 1);
+        ptr::write_volatile(
+            CONTROL_REG as *mut u32,
+            CONTROL_ENABLE | CONTROL_RESET_ENABLE,
+        );
+    });
+    Ok(())
+}
+
+/// Stops the watchdog
+pub fn disable() {
+    unlocked(|| unsafe {
+        ptr::write_volatile(CONTROL_REG as *mut u32, 0);
+        ptr::write_volatile(INTCLR_REG as *mut u32, 1);
+    });
+    disable_interrupt_on(Interrupt::WDOG, watcher(), ExecutionMode::Machine);
+}
+
+/// Reloads the counter, pushing the timeout out again
+pub fn feed() {
+    unlocked(|| unsafe { ptr::write_volatile(INTCLR_REG as *mut u32, 1) });
+    //Fed late, after the interrupt gave up on it
+    if EXPIRED.swap(false, Ordering::Relaxed) {
+        crash::discard_watchdog();
+        //Routed before, enable() already reported harts it couldn't use
+        let _ = enable_interrupt_on(
+            Interrupt::WDOG,
+            InterruptPriority::Priority7,
+            watcher(),
+            ExecutionMode::Machine,
+        );
+    }
+}
+
+/// Write protects the registers against stray writes.  [feed], [enable] and
+/// [disable] unlock them for their own access only.
+pub fn lock() {
+    LOCKED.store(true, Ordering::Relaxed);
+    unsafe { ptr::write_volatile(LOCK_REG as *mut u32, LOCK_KEY) };
+}
+
+/// Sets the function called from the `WDOG` interrupt half way through the
+/// timeout.  It may [feed] the watchdog to keep the SoC running.
+pub fn set_pre_timeout_callback(callback: Option<fn()>) {
+    riscv::interrupt::free(|| {
+        *PRE_TIMEOUT_CALLBACK.lock() = callback;
+    });
+}
+
+/// Time left until the counter reaches zero
+pub fn remaining() -> Duration {
+    let ticks = unsafe { ptr::read_volatile(VALUE_REG as *const u32) } as u128;
+    Duration::from_nanos((ticks * NANOS_PER_SECOND as u128 / CLK_CORE_HZ as u128) as u64)
+}
+
+fn watchdog_isr() {
+    let callback = *PRE_TIMEOUT_CALLBACK.lock();
+    if let Some(callback) = callback {
+        callback();
+    }
+    let fed = unsafe { ptr::read_volatile(RIS_REG as *const u32) } & 1 == 0;
+    if !fed {
+        crash::record_watchdog(format_args!("not fed within the timeout"));
+        //The interrupt stays raised until the reset or a late feed, stop it
+        //from storming
+        EXPIRED.store(true, Ordering::Relaxed);
+        disable_interrupt_on(Interrupt::WDOG, watcher(), ExecutionMode::Machine);
+    }
+}
+
+/// Resets the SoC by letting the watchdog expire right away.  The JH7110 has no
+/// other reset this code can reach from machine mode.
+pub fn reset_soc() -> ! {
+    bring_up();
+    unsafe {
+        ptr::write_volatile(LOCK_REG as *mut u32, UNLOCK_KEY);
+        //The counter interrupts at the first expiry and resets at the second
+        ptr::write_volatile(LOAD_REG as *mut u32, 1);
+        ptr::write_volatile(
+            CONTROL_REG as *mut u32,
+            CONTROL_ENABLE | CONTROL_RESET_ENABLE,
+        );
+    }
+    loop {
+        riscv::asm::wfi();
+    }
+}